@@ -0,0 +1,274 @@
+use std::{f64::consts::PI, time::Duration};
+
+use chrono::{DateTime, Utc};
+use embedded_graphics::{pixelcolor::Rgb888, prelude::RgbColor};
+use log::{debug, info};
+use tokio::{
+    spawn,
+    sync::watch::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::firebase::{
+    find_settings_key, get_latest_widget, BrightnessSettings, WidgetList, WidgetSnapshot,
+};
+
+const RELOAD_SECONDS: u64 = 60;
+
+// Degrees of the sun above the horizon. Night fades in from sunset to the end of civil twilight.
+const SUNSET_ELEVATION: f64 = -0.833;
+const NIGHT_ELEVATION: f64 = -6.0;
+
+// How every color is drawn right now
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brightness {
+    pub percent: u8,
+    pub night_palette: bool,
+}
+
+impl Brightness {
+    pub const FULL: Brightness = Brightness {
+        percent: 100,
+        night_palette: false,
+    };
+
+    pub fn apply(&self, color: Rgb888) -> Rgb888 {
+        let color = match self.night_palette {
+            true => get_night_color(color),
+            false => color,
+        };
+        let scale = |channel: u8| (channel as u32 * self.percent.min(100) as u32 / 100) as u8;
+        Rgb888::new(scale(color.r()), scale(color.g()), scale(color.b()))
+    }
+}
+
+// Half way to gray and a quarter darker, so line colors are softer but still tell lines apart
+fn get_night_color(color: Rgb888) -> Rgb888 {
+    let gray = (color.r() as u32 * 3 + color.g() as u32 * 6 + color.b() as u32) / 10;
+    let dim = |channel: u8| ((channel as u32 + gray) * 3 / 8) as u8;
+    Rgb888::new(dim(color.r()), dim(color.g()), dim(color.b()))
+}
+
+pub fn get_brightness(settings: &BrightnessSettings, now: DateTime<Utc>) -> Brightness {
+    let night = get_night_amount(settings, now);
+    let day_percent = settings.get_day_percent() as f64;
+    let night_percent = settings.get_night_percent() as f64;
+    Brightness {
+        percent: settings
+            .override_percent
+            .map(|percent| percent.min(100))
+            .unwrap_or((day_percent + (night_percent - day_percent) * night).round() as u8),
+        night_palette: settings.uses_night_palette() && night >= 0.5,
+    }
+}
+
+// 0 during the day, 1 at night and in between during twilight
+fn get_night_amount(settings: &BrightnessSettings, now: DateTime<Utc>) -> f64 {
+    if let Some(window) = &settings.night {
+        let local = now.with_timezone(&settings.get_timezone()).naive_local();
+        return match window.contains(local) {
+            true => 1.0,
+            false => 0.0,
+        };
+    }
+    let (Some(latitude), Some(longitude)) = (settings.latitude, settings.longitude) else {
+        return 0.0;
+    };
+    let elevation = get_solar_elevation(latitude, longitude, now);
+    ((SUNSET_ELEVATION - elevation) / (SUNSET_ELEVATION - NIGHT_ELEVATION)).clamp(0.0, 1.0)
+}
+
+// In degrees, from the low precision formulas of the Astronomical Almanac. Sunrise and sunset
+// come out within a few minutes, which is plenty for dimming a sign.
+pub fn get_solar_elevation(latitude: f64, longitude: f64, now: DateTime<Utc>) -> f64 {
+    let days = now.timestamp() as f64 / 86400.0 - 10957.5; // Since J2000
+    let mean_anomaly = (357.529 + 0.98560028 * days).to_radians();
+    let mean_longitude = 280.459 + 0.98564736 * days;
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.00000036 * days).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let sidereal_time = (280.46061837 + 360.98564736629 * days + longitude).to_radians();
+    let hour_angle = (sidereal_time - right_ascension).rem_euclid(2.0 * PI);
+
+    let latitude = latitude.to_radians();
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+pub fn spawn_brightness_update_task(
+    key: String,
+    mut widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    settings_tx: Sender<Option<BrightnessSettings>>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        loop {
+            let snapshot = widgets_rx.borrow_and_update().clone();
+            let settings = get_latest_widget::<BrightnessSettings>(snapshot, &key)
+                .await
+                .map(|mut settings| {
+                    if let Some(night) = &mut settings.night {
+                        night.validate();
+                    }
+                    settings
+                });
+            debug!(target: "brightness_update", "Loaded brightness settings {}: {:?}", key, settings);
+            settings_tx.send_if_modified(|current| {
+                let modified = *current != settings;
+                *current = settings;
+                modified
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(RELOAD_SECONDS)) => {},
+                _ = widgets_rx.changed() => {},
+            }
+        }
+    })
+}
+
+struct RunningSettings {
+    key: String,
+    settings_rx: Receiver<Option<BrightnessSettings>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for RunningSettings {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Follows the brightness settings in Firebase. Full brightness while there are none.
+pub struct BrightnessControl {
+    settings: Option<RunningSettings>,
+    widgets_rx: Receiver<Option<WidgetSnapshot>>,
+}
+
+impl BrightnessControl {
+    pub fn new(widgets_rx: Receiver<Option<WidgetSnapshot>>) -> Self {
+        BrightnessControl {
+            settings: None,
+            widgets_rx,
+        }
+    }
+
+    pub fn sync(&mut self, list: &WidgetList) {
+        let key = find_settings_key::<BrightnessSettings>(list);
+        if self.settings.as_ref().map(|settings| &settings.key) == key.as_ref() {
+            return;
+        }
+        self.settings = key.map(|key| {
+            info!(target: "brightness_update", "Using brightness settings {}", key);
+            let (settings_tx, settings_rx) = watch::channel(None);
+            RunningSettings {
+                task: spawn_brightness_update_task(
+                    key.clone(),
+                    self.widgets_rx.clone(),
+                    settings_tx,
+                ),
+                key,
+                settings_rx,
+            }
+        });
+    }
+
+    pub fn get_brightness(&self, now: DateTime<Utc>) -> Brightness {
+        self.settings
+            .as_ref()
+            .and_then(|running| {
+                let settings = running.settings_rx.borrow();
+                settings
+                    .as_ref()
+                    .map(|settings| get_brightness(settings, now))
+            })
+            .unwrap_or(Brightness::FULL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use serde_json::json;
+
+    use crate::firebase::fixture_config;
+
+    use super::*;
+
+    const WASHINGTON: (f64, f64) = (38.8951, -77.0364);
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn settings(settings: serde_json::Value) -> BrightnessSettings {
+        fixture_config(settings)
+    }
+
+    // Sunset in Washington was at 20:37 EDT on 21 June 2024 and 16:49 EST on 21 December 2024
+    #[test]
+    fn solar_elevation_crosses_sunset_on_time() {
+        let (latitude, longitude) = WASHINGTON;
+        for sunset in [at(2024, 6, 22, 0, 37), at(2024, 12, 21, 21, 49)] {
+            let before = get_solar_elevation(latitude, longitude, sunset - TimeDelta::minutes(3));
+            let after = get_solar_elevation(latitude, longitude, sunset + TimeDelta::minutes(3));
+            assert!(before > SUNSET_ELEVATION, "{} at {}", before, sunset);
+            assert!(after < SUNSET_ELEVATION, "{} at {}", after, sunset);
+        }
+    }
+
+    #[test]
+    fn solar_elevation_at_noon() {
+        let (latitude, longitude) = WASHINGTON;
+        // Around the equinox the sun is as high as the latitude is far from the pole
+        let elevation = get_solar_elevation(latitude, longitude, at(2024, 3, 20, 17, 8));
+        assert!((elevation - (90.0 - latitude)).abs() < 1.0, "{}", elevation);
+    }
+
+    #[test]
+    fn night_palette_keeps_line_colors_apart() {
+        let night = Brightness {
+            percent: 100,
+            night_palette: true,
+        };
+        let red = night.apply(Rgb888::new(255, 0, 0));
+        let blue = night.apply(Rgb888::new(0, 0, 255));
+        assert!(red.r() > red.g() && red.r() > red.b(), "{:?}", red);
+        assert!(blue.b() > blue.r() && blue.b() > blue.g(), "{:?}", blue);
+        assert!(night.apply(Rgb888::WHITE).r() < 255);
+    }
+
+    #[test]
+    fn brightness_fades_through_twilight() {
+        let settings = settings(json!({
+            "day_percent": 100,
+            "night_percent": 20,
+            "latitude": WASHINGTON.0,
+            "longitude": WASHINGTON.1,
+            "night_palette": true,
+        }));
+        let day = get_brightness(&settings, at(2024, 6, 21, 17, 0));
+        assert_eq!(
+            day,
+            Brightness {
+                percent: 100,
+                night_palette: false
+            }
+        );
+        let night = get_brightness(&settings, at(2024, 6, 22, 4, 0));
+        assert_eq!(
+            night,
+            Brightness {
+                percent: 20,
+                night_palette: true
+            }
+        );
+        let dusk = get_brightness(&settings, at(2024, 6, 22, 0, 50));
+        assert!(dusk.percent > 20 && dusk.percent < 100, "{:?}", dusk);
+    }
+}
@@ -1,11 +1,22 @@
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, error::Error, time::Duration};
 
+use chrono::{Datelike, NaiveDateTime, NaiveTime, TimeDelta, Weekday};
+use chrono_tz::Tz;
 use firebase_rs::Firebase;
+use itertools::Itertools;
+use log::{debug, error, info, warn};
 use retry::delay::{jitter, Exponential};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Widget {
+    #[serde(default)]
     name: String,
 }
 
@@ -17,14 +28,161 @@ pub struct Alert {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AlertWidget {
     name: String,
+    #[serde(default)] // Firebase drops empty lists
     pub alerts: Vec<Alert>,
+    pub show_incidents: Option<bool>, // Mix in WMATA rail incidents
+    pub incident_lines: Option<Vec<String>>, // Only incidents affecting these lines, e.g. ["RD", "OR"]
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ArrivalWidget {
     name: String,
     pub station_id: String,
+    pub station_ids: Option<Vec<String>>, // Transfer stations have one code per level, e.g. A01 and C01
     pub messages: Option<Vec<ArrivalMessage>>,
+    pub walk_minutes: Option<i64>,
+    pub line_walk_minutes: Option<HashMap<String, i64>>, // Keyed by line code, e.g. "RD"
+    pub hide_uncatchable: Option<bool>,
+    pub filters: Option<ArrivalFilters>,
+    pub paging: Option<ArrivalPaging>,
+    pub stale_minutes: Option<i64>, // How old predictions can get before the board says so
+    pub destination_station: Option<String>, // Trip mode: only trains stopping here, with their ETA
+    pub status_bar: Option<ClockSettings>, // A row above the column headings with the date and time
+    pub text_overflow: Option<TextOverflow>,
+}
+
+// What the board does with a destination or message too long for its column
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextOverflow {
+    #[default]
+    Marquee, // Scrolls through it
+    Ellipsis, // Cuts it short
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClockSettings {
+    pub timezone: Option<String>, // IANA name, e.g. "America/New_York"
+    pub use_24_hour: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClockWidget {
+    name: String,
+    #[serde(flatten)]
+    pub clock: ClockSettings,
+    pub show_when_idle: Option<bool>, // Whenever the arrival board has nothing to show
+    pub display_seconds: Option<u64>,
+    pub interval_seconds: Option<u64>, // Between showings. Unset only shows the clock when idle.
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ArrivalPaging {
+    pub enabled: bool,
+    pub interval_seconds: Option<i64>,
+    pub pin_sticky: Option<bool>, // Repeat sticky messages at the top of every page
+}
+
+// Empty or missing include lists let everything through
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ArrivalFilters {
+    pub include_lines: Option<Vec<String>>,
+    pub exclude_lines: Option<Vec<String>>,
+    pub include_groups: Option<Vec<String>>, // Track group, usually "1" or "2"
+    pub exclude_groups: Option<Vec<String>>,
+    pub include_destinations: Option<Vec<String>>, // Destination station codes
+    pub exclude_destinations: Option<Vec<String>>,
+    pub hide_no_passenger: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BusWidget {
+    name: String,
+    pub stop_id: String,
+    pub stop_ids: Option<Vec<String>>,
+    pub routes: Option<Vec<String>>, // Only these routes, e.g. ["D6", "30N"]. Empty shows all.
+    pub walk_minutes: Option<i64>,
+    pub hide_uncatchable: Option<bool>,
+    pub paging: Option<ArrivalPaging>,
+    pub stale_minutes: Option<i64>,
+    pub text_overflow: Option<TextOverflow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LineMapWidget {
+    name: String,
+    pub line: String, // Line code, e.g. "RD"
+    pub station_id: String,
+    pub display_seconds: Option<u64>,
+    pub interval_seconds: Option<u64>, // Between showings
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ElevatorWidget {
+    name: String,
+    pub station_id: String,
+    pub station_ids: Option<Vec<String>>,
+    pub elevators_only: Option<bool>,
+    pub display_seconds: Option<u64>,  // Per page of outages
+    pub interval_seconds: Option<u64>, // Between showings
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BikeshareWidget {
+    name: String,
+    pub feed: Option<String>, // Base URL of the GBFS feeds, or a local directory with their JSON files
+    pub station_ids: Vec<String>,
+    pub names: Option<HashMap<String, String>>, // Shorter names to show, keyed by station ID
+    pub stale_minutes: Option<i64>,
+    pub text_overflow: Option<TextOverflow>, // For names too long for their column
+    pub display_seconds: Option<u64>,
+    pub interval_seconds: Option<u64>, // Between showings
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScreenPlaylist {
+    name: String,
+    #[serde(default)] // Firebase drops empty lists
+    pub entries: Vec<PlaylistEntry>,
+    pub timezone: Option<String>, // For the schedule windows. IANA name, e.g. "America/New_York"
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub widget: String, // Push key of one widget, or a widget name to show any widget of that kind
+    pub seconds: Option<u64>,
+    pub weight: Option<u32>, // How often it's picked compared to the other entries. 0 never is.
+    pub windows: Option<Vec<ScheduleWindow>>, // When it may be shown. Unset is any time.
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduleWindow {
+    pub days: Option<Vec<String>>, // e.g. ["Mon", "Fri"]. Unset is every day.
+    pub start: Option<String>,     // e.g. "16:00". Unset is midnight.
+    pub end: Option<String>, // e.g. "19:00". Unset is the end of the day, before start runs past midnight.
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BrightnessSettings {
+    name: String,
+    pub day_percent: Option<u8>,
+    pub night_percent: Option<u8>,
+    pub override_percent: Option<u8>, // Fixed brightness, whatever the time of day
+    pub night: Option<ScheduleWindow>, // Fixed night hours. Otherwise night is sunset to sunrise at the coordinates.
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub timezone: Option<String>,    // For the night hours
+    pub night_palette: Option<bool>, // Draw softer, dimmer colors at night
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WeatherWidget {
+    name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub units: Option<String>, // "imperial" (default) or "metric"
+    pub display_seconds: Option<u64>,
+    pub interval_seconds: Option<u64>, // Between showings
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -32,8 +190,19 @@ pub struct ArrivalMessage {
     pub message: String,
     pub sticky: bool,
     pub time: i64,
+    #[serde(skip)]
+    pub walk_time: TimeDelta, // Filled in from the widget when converting for display
 }
 
+const DEFAULT_WALK_MINUTES: i64 = 15;
+const DEFAULT_STALE_MINUTES: i64 = 2;
+const DEFAULT_BUS_WALK_MINUTES: i64 = 3;
+const DEFAULT_TIMEZONE: Tz = chrono_tz::America::New_York;
+const DEFAULT_PLAYLIST_SECONDS: u64 = 15;
+const DEFAULT_DAY_PERCENT: u8 = 100;
+const DEFAULT_NIGHT_PERCENT: u8 = 30;
+const DEFAULT_GBFS_FEED: &str = "https://gbfs.lyft.com/gbfs/2.3/dca/en"; // Capital Bikeshare
+
 impl AlertWidget {
     pub fn get_messages(&self) -> Vec<String> {
         self.alerts
@@ -42,6 +211,268 @@ impl AlertWidget {
             .map(|alert| alert.message)
             .collect::<Vec<String>>()
     }
+
+    pub fn should_show_incidents(&self) -> bool {
+        self.show_incidents.unwrap_or(false)
+    }
+
+    pub fn wants_incident_lines(&self, lines: &[&str]) -> bool {
+        match &self.incident_lines {
+            Some(wanted) if !wanted.is_empty() => lines
+                .iter()
+                .any(|line| wanted.iter().any(|w| w.eq_ignore_ascii_case(line))),
+            _ => true,
+        }
+    }
+}
+
+impl ArrivalWidget {
+    pub fn get_walk_minutes(&self, line: &str) -> i64 {
+        self.line_walk_minutes
+            .as_ref()
+            .and_then(|overrides| overrides.get(line).copied())
+            .or(self.walk_minutes)
+            .unwrap_or(DEFAULT_WALK_MINUTES)
+    }
+
+    pub fn get_station_codes(&self) -> Vec<String> {
+        merge_station_codes(&self.station_id, &self.station_ids)
+    }
+
+    pub fn get_stale_minutes(&self) -> i64 {
+        self.stale_minutes.unwrap_or(DEFAULT_STALE_MINUTES)
+    }
+
+    pub fn should_hide_uncatchable(&self) -> bool {
+        self.hide_uncatchable.unwrap_or(false)
+    }
+
+    // Trip arrival times and fares use the status bar's timezone
+    pub fn get_timezone(&self) -> Tz {
+        self.status_bar
+            .as_ref()
+            .map(ClockSettings::get_timezone)
+            .unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    // Transfer stations have a code per level, e.g. "F03,D03" for L'Enfant Plaza
+    pub fn get_destination_codes(&self) -> Vec<String> {
+        self.destination_station
+            .as_deref()
+            .map(|destination| merge_station_codes(destination, &None))
+            .unwrap_or_default()
+    }
+}
+
+impl ClockSettings {
+    pub fn get_timezone(&self) -> Tz {
+        parse_timezone(&self.timezone)
+    }
+
+    pub fn is_24_hour(&self) -> bool {
+        self.use_24_hour.unwrap_or(false)
+    }
+}
+
+fn parse_timezone(timezone: &Option<String>) -> Tz {
+    match timezone {
+        Some(name) => name.parse().unwrap_or_else(|_| {
+            warn!(target: "firebase", "Unknown timezone {}, using {}", name, DEFAULT_TIMEZONE);
+            DEFAULT_TIMEZONE
+        }),
+        None => DEFAULT_TIMEZONE,
+    }
+}
+
+impl ClockWidget {
+    pub fn should_show_when_idle(&self) -> bool {
+        self.show_when_idle.unwrap_or(false)
+    }
+}
+
+impl BusWidget {
+    pub fn get_walk_minutes(&self) -> i64 {
+        self.walk_minutes.unwrap_or(DEFAULT_BUS_WALK_MINUTES)
+    }
+
+    pub fn get_stop_ids(&self) -> Vec<String> {
+        merge_station_codes(&self.stop_id, &self.stop_ids)
+    }
+
+    pub fn get_stale_minutes(&self) -> i64 {
+        self.stale_minutes.unwrap_or(DEFAULT_STALE_MINUTES)
+    }
+
+    pub fn should_hide_uncatchable(&self) -> bool {
+        self.hide_uncatchable.unwrap_or(false)
+    }
+
+    pub fn wants_route(&self, route: &str) -> bool {
+        filter_allows(&self.routes, &None, Some(route))
+    }
+}
+
+impl ElevatorWidget {
+    pub fn get_station_codes(&self) -> Vec<String> {
+        merge_station_codes(&self.station_id, &self.station_ids)
+    }
+}
+
+impl BikeshareWidget {
+    pub fn get_feed(&self) -> &str {
+        self.feed.as_deref().unwrap_or(DEFAULT_GBFS_FEED)
+    }
+
+    pub fn get_stale_minutes(&self) -> i64 {
+        self.stale_minutes.unwrap_or(DEFAULT_STALE_MINUTES)
+    }
+}
+
+impl ScreenPlaylist {
+    pub fn get_timezone(&self) -> Tz {
+        parse_timezone(&self.timezone)
+    }
+
+    pub fn validate(&mut self) {
+        self.entries
+            .iter_mut()
+            .flat_map(|entry| entry.windows.iter_mut().flatten())
+            .for_each(ScheduleWindow::validate);
+    }
+}
+
+impl PlaylistEntry {
+    pub fn get_duration(&self) -> TimeDelta {
+        TimeDelta::seconds(self.seconds.unwrap_or(DEFAULT_PLAYLIST_SECONDS) as i64)
+    }
+
+    pub fn get_weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+
+    pub fn is_scheduled(&self, local: NaiveDateTime) -> bool {
+        match &self.windows {
+            Some(windows) if !windows.is_empty() => {
+                windows.iter().any(|window| window.contains(local))
+            }
+            _ => true,
+        }
+    }
+}
+
+impl ScheduleWindow {
+    // Days are matched against the current local date, also for the part of a window after midnight
+    pub fn contains(&self, local: NaiveDateTime) -> bool {
+        let on_day = match &self.days {
+            Some(days) if !days.is_empty() => days
+                .iter()
+                .any(|day| day.parse::<Weekday>().ok() == Some(local.weekday())),
+            _ => true,
+        };
+        let time = local.time();
+        let start = self
+            .start
+            .as_deref()
+            .and_then(parse_time)
+            .unwrap_or(NaiveTime::MIN);
+        let in_window = match self.end.as_deref().and_then(parse_time) {
+            Some(end) if end < start => time >= start || time < end,
+            Some(end) => time >= start && time < end,
+            None => time >= start,
+        };
+        on_day && in_window
+    }
+
+    // Checked once when the settings load, since windows are matched on every frame. Times that
+    // don't parse are dropped, so they count as unset. Unknown days never match.
+    pub fn validate(&mut self) {
+        for time in [&mut self.start, &mut self.end] {
+            if let Some(value) = time.as_deref().filter(|value| parse_time(value).is_none()) {
+                warn!(target: "firebase", "Could not parse time {}, expected HH:MM", value);
+                *time = None;
+            }
+        }
+        for day in self.days.iter().flatten() {
+            if day.parse::<Weekday>().is_err() {
+                warn!(target: "firebase", "Unknown day {}, expected e.g. Mon", day);
+            }
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+impl BrightnessSettings {
+    pub fn get_day_percent(&self) -> u8 {
+        self.day_percent.unwrap_or(DEFAULT_DAY_PERCENT).min(100)
+    }
+
+    pub fn get_night_percent(&self) -> u8 {
+        self.night_percent.unwrap_or(DEFAULT_NIGHT_PERCENT).min(100)
+    }
+
+    pub fn get_timezone(&self) -> Tz {
+        parse_timezone(&self.timezone)
+    }
+
+    pub fn uses_night_palette(&self) -> bool {
+        self.night_palette.unwrap_or(false)
+    }
+}
+
+impl WeatherWidget {
+    pub fn uses_metric(&self) -> bool {
+        self.units.as_deref() == Some("metric")
+    }
+}
+
+fn merge_station_codes(station_id: &str, station_ids: &Option<Vec<String>>) -> Vec<String> {
+    station_id
+        .split(',')
+        .chain(station_ids.iter().flatten().map(String::as_str))
+        .map(|code| code.trim().to_uppercase())
+        .filter(|code| !code.is_empty())
+        .unique()
+        .collect()
+}
+
+impl ArrivalFilters {
+    pub fn allows(
+        &self,
+        line: &str,
+        group: &str,
+        destination_code: Option<&str>,
+        is_no_passenger: bool,
+    ) -> bool {
+        if is_no_passenger && self.hide_no_passenger.unwrap_or(false) {
+            return false;
+        }
+        filter_allows(&self.include_lines, &self.exclude_lines, Some(line))
+            && filter_allows(&self.include_groups, &self.exclude_groups, Some(group))
+            && filter_allows(
+                &self.include_destinations,
+                &self.exclude_destinations,
+                destination_code,
+            )
+    }
+}
+
+fn filter_allows(
+    include: &Option<Vec<String>>,
+    exclude: &Option<Vec<String>>,
+    value: Option<&str>,
+) -> bool {
+    let matches = |list: &Vec<String>| {
+        value.is_some_and(|value| list.iter().any(|v| v.eq_ignore_ascii_case(value)))
+    };
+    let included = match include {
+        Some(list) if !list.is_empty() => matches(list),
+        _ => true,
+    };
+    let excluded = exclude.as_ref().is_some_and(matches);
+    included && !excluded
 }
 
 macro_rules! retry {
@@ -66,40 +497,97 @@ macro_rules! retry {
     };
 }
 
-pub trait LoadableWidget {
-    async fn load() -> Self;
-}
+// The `widgets` node as last received from the stream, keyed by Firebase push key
+pub type WidgetSnapshot = HashMap<String, Value>;
 
-impl LoadableWidget for ArrivalWidget {
-    async fn load() -> Self {
-        let widgets = get_widgets().await;
-        let key = widgets
-            .keys()
-            .find(|&k| widgets.get(k).unwrap().name == "DCMetroTrainArrivalWidget")
-            .unwrap();
+pub trait LoadableWidget: DeserializeOwned {
+    const NAME: &'static str;
 
-        retry! {
-            get_firebase().at("widgets").at(key).get::<ArrivalWidget>().await
+    // None if the widget is gone or can't be fetched, so the caller can show it as disabled
+    async fn load(key: &str) -> Option<Self> {
+        let value = retry! {
+            get_firebase().at("widgets").at(key).get::<Value>().await
+        };
+        match value {
+            Ok(value) if value.get("name").and_then(Value::as_str) == Some(Self::NAME) => {
+                Self::parse(value)
+            }
+            Ok(_) => {
+                debug!(target: "firebase", "Key {} no longer holds {}", key, Self::NAME);
+                None
+            }
+            Err(err) => {
+                error!(target: "firebase", "Could not get {} from firebase: {}", Self::NAME, err);
+                None
+            }
         }
-        .expect("Could not get ArrivalWidget from firebase")
     }
-}
 
-impl LoadableWidget for AlertWidget {
-    async fn load() -> Self {
-        let widgets = get_widgets().await;
-        let key = widgets
-            .keys()
-            .find(|&k| widgets.get(k).unwrap().name == "DCMetroAlertsWidget")
-            .unwrap();
+    fn from_snapshot(snapshot: &WidgetSnapshot, key: &str) -> Option<Self> {
+        let value = snapshot
+            .get(key)
+            .filter(|v| v.get("name").and_then(Value::as_str) == Some(Self::NAME))?;
+        Self::parse(value.clone())
+    }
 
-        retry! {
-            get_firebase().at("widgets").at(key).get::<AlertWidget>().await
+    fn parse(value: Value) -> Option<Self> {
+        match serde_json::from_value(value) {
+            Ok(widget) => Some(widget),
+            Err(err) => {
+                error!(target: "firebase", "Could not parse {}: {}", Self::NAME, err);
+                None
+            }
         }
-        .expect("Could not get AlertWidget from firebase")
     }
 }
 
+impl LoadableWidget for ArrivalWidget {
+    const NAME: &'static str = "DCMetroTrainArrivalWidget";
+}
+
+impl LoadableWidget for AlertWidget {
+    const NAME: &'static str = "DCMetroAlertsWidget";
+}
+
+impl LoadableWidget for BusWidget {
+    const NAME: &'static str = "DCMetroBusArrivalWidget";
+}
+
+impl LoadableWidget for LineMapWidget {
+    const NAME: &'static str = "DCMetroLineMapWidget";
+}
+
+impl LoadableWidget for ClockWidget {
+    const NAME: &'static str = "DCMetroClockWidget";
+}
+
+impl LoadableWidget for ElevatorWidget {
+    const NAME: &'static str = "DCMetroElevatorOutageWidget";
+}
+
+impl LoadableWidget for BikeshareWidget {
+    const NAME: &'static str = "DCMetroBikeshareWidget";
+}
+
+impl LoadableWidget for ScreenPlaylist {
+    const NAME: &'static str = "DCMetroScreenPlaylist";
+}
+
+impl LoadableWidget for BrightnessSettings {
+    const NAME: &'static str = "DCMetroBrightnessSettings";
+}
+
+impl LoadableWidget for WeatherWidget {
+    const NAME: &'static str = "DCMetroWeatherWidget";
+}
+
+// A config as Firebase would store it, named for the widget it's parsed as
+#[cfg(test)]
+pub fn fixture_config<W: LoadableWidget>(mut config: Value) -> W {
+    config["name"] = Value::from(W::NAME);
+    W::parse(config).unwrap()
+}
+
 fn get_firebase() -> Firebase {
     Firebase::auth(
         &env::var("FIREBASE_URL").unwrap(),
@@ -108,13 +596,510 @@ fn get_firebase() -> Firebase {
     .unwrap()
 }
 
-async fn get_widgets() -> HashMap<String, Widget> {
+async fn get_widgets() -> Option<HashMap<String, Widget>> {
     let firebase = get_firebase().at("widgets");
 
     let widgets = retry! {{
         firebase.get::<HashMap<String, Widget>>().await
-    }}
-    .expect("Could not connect to Firebase after retries");
+    }};
+    match widgets {
+        Ok(widgets) => Some(widgets),
+        Err(err) => {
+            error!(target: "firebase", "Could not connect to Firebase after retries: {}", err);
+            None
+        }
+    }
+}
+
+// Once the stream is live its copy is authoritative, otherwise fall back to a REST request
+pub async fn get_latest_widget<T: LoadableWidget>(
+    snapshot: Option<WidgetSnapshot>,
+    key: &str,
+) -> Option<T> {
+    match snapshot {
+        Some(snapshot) => T::from_snapshot(&snapshot, key),
+        None => T::load(key).await,
+    }
+}
+
+// The raw config under `key`, to tell edits to this widget apart from edits to the others
+pub fn get_widget_config(snapshot: &Option<WidgetSnapshot>, key: &str) -> Option<Value> {
+    snapshot
+        .as_ref()
+        .and_then(|snapshot| snapshot.get(key))
+        .cloned()
+}
+
+// Resolves once the config under `key` differs from `config`. Never resolves if the stream ends.
+pub async fn wait_for_widget_edit(
+    widgets_rx: &mut Receiver<Option<WidgetSnapshot>>,
+    key: &str,
+    config: &Option<Value>,
+) {
+    while widgets_rx.changed().await.is_ok() {
+        if get_widget_config(&widgets_rx.borrow_and_update(), key) != *config {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+// Push key to widget name, for every entry under `widgets`
+pub type WidgetList = HashMap<String, String>;
+
+// For documents that configure the whole sign, of which only one is used. With several, the first
+// by push key wins.
+pub fn find_settings_key<T: LoadableWidget>(list: &WidgetList) -> Option<String> {
+    let keys: Vec<&String> = list
+        .iter()
+        .filter(|(_, name)| name.as_str() == T::NAME)
+        .map(|(key, _)| key)
+        .sorted()
+        .collect();
+    if keys.len() > 1 {
+        warn!(target: "firebase", "{} {} documents configured, using {}", keys.len(), T::NAME, keys[0]);
+    }
+    keys.first().map(|key| key.to_string())
+}
+
+const WIDGET_LIST_POLL: Duration = Duration::from_secs(60);
 
-    widgets
+// Publishes which widgets are configured. Taken from the stream while it's live, polled over REST until then.
+pub fn spawn_widget_list_task(
+    mut snapshot_rx: Receiver<Option<WidgetSnapshot>>,
+    list_tx: Sender<Option<WidgetList>>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        loop {
+            let snapshot = snapshot_rx.borrow_and_update().clone();
+            let list = match snapshot {
+                Some(snapshot) => Some(
+                    snapshot
+                        .into_iter()
+                        .filter_map(|(key, value)| {
+                            let name = value.get("name")?.as_str()?.to_string();
+                            Some((key, name))
+                        })
+                        .collect(),
+                ),
+                None => get_widgets().await.map(|widgets| {
+                    widgets
+                        .into_iter()
+                        .map(|(key, widget)| (key, widget.name))
+                        .collect()
+                }),
+            };
+            if let Some(list) = list {
+                list_tx.send_if_modified(|current| {
+                    let modified = current.as_ref() != Some(&list);
+                    *current = Some(list);
+                    modified
+                });
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(WIDGET_LIST_POLL) => {}
+                Ok(_) = snapshot_rx.changed() => {}
+            }
+        }
+    })
+}
+
+const STREAM_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(90); // Firebase sends keep-alive every 30s
+const STREAM_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// Follows the `widgets` node over the Realtime Database REST streaming API (server-sent events)
+// and publishes a fresh snapshot after every change.
+pub fn spawn_widget_stream(snapshot_tx: Sender<Option<WidgetSnapshot>>) -> JoinHandle<()> {
+    spawn(async move {
+        let mut backoff = Exponential::from_millis(500).map(jitter);
+        loop {
+            let mut connected = false;
+            match follow_widget_stream(&snapshot_tx, &mut connected).await {
+                Ok(_) => warn!(target: "firebase_stream", "Stream closed by server"),
+                Err(err) => error!(target: "firebase_stream", "Stream error: {}", err),
+            }
+            if connected {
+                backoff = Exponential::from_millis(500).map(jitter);
+            }
+            let delay = backoff.next().unwrap().min(STREAM_MAX_BACKOFF);
+            info!(target: "firebase_stream", "Reconnecting in {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+    })
+}
+
+async fn follow_widget_stream(
+    snapshot_tx: &Sender<Option<WidgetSnapshot>>,
+    connected: &mut bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let url = format!(
+        "{}/widgets.json?auth={}",
+        env::var("FIREBASE_URL")?.trim_end_matches('/'),
+        env::var("FIREBASE_API_KEY")?
+    );
+    let mut resp = reqwest::Client::new()
+        .get(url)
+        .header("Accept", "text/event-stream")
+        .send()
+        .await?
+        .error_for_status()?;
+    *connected = true;
+    info!(target: "firebase_stream", "Connected");
+
+    let mut tree = Value::Null;
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut event = String::new();
+    let mut data = String::new();
+    while let Some(chunk) = tokio::time::timeout(STREAM_KEEP_ALIVE_TIMEOUT, resp.chunk()).await?? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if let Some(value) = line.strip_prefix("event:") {
+                event = value.trim().to_string();
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data.push_str(value.trim());
+            } else if line.is_empty() && !event.is_empty() {
+                match event.as_str() {
+                    "put" | "patch" => {
+                        apply_stream_event(&mut tree, &event, &data)?;
+                        let snapshot = match &tree {
+                            Value::Object(map) => map.clone().into_iter().collect(),
+                            _ => WidgetSnapshot::new(),
+                        };
+                        snapshot_tx.send_replace(Some(snapshot));
+                    }
+                    "keep-alive" => {}
+                    "cancel" | "auth_revoked" => {
+                        return Err(format!("Stream ended with {}: {}", event, data).into())
+                    }
+                    _ => debug!(target: "firebase_stream", "Ignoring event {}", event),
+                }
+                event.clear();
+                data.clear();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct StreamEvent {
+    path: String,
+    data: Value,
+}
+
+fn apply_stream_event(
+    tree: &mut Value,
+    event: &str,
+    data: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let StreamEvent { path, data } = serde_json::from_str(data)?;
+    let path: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    debug!(target: "firebase_stream", "{} at /{}", event, path.join("/"));
+
+    if event == "patch" {
+        // A patch is a put of each child it contains
+        if let Value::Object(children) = data {
+            for (key, value) in children {
+                let mut child_path = path.clone();
+                child_path.push(&key);
+                set_at_path(tree, &child_path, value);
+            }
+        }
+    } else {
+        set_at_path(tree, &path, data);
+    }
+    Ok(())
+}
+
+// Writing null deletes, matching the Realtime Database semantics
+fn set_at_path(tree: &mut Value, path: &[&str], value: Value) {
+    let Some((last, parents)) = path.split_last() else {
+        *tree = value;
+        return;
+    };
+    let mut node = tree;
+    for key in parents {
+        if !node.is_object() {
+            *node = Value::Object(Default::default());
+        }
+        node = node
+            .as_object_mut()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert(Value::Null);
+    }
+    if !node.is_object() {
+        *node = Value::Object(Default::default());
+    }
+    let map = node.as_object_mut().unwrap();
+    if value.is_null() {
+        map.remove(*last);
+    } else {
+        map.insert(last.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    use super::*;
+
+    fn window(days: Option<&[&str]>, start: Option<&str>, end: Option<&str>) -> ScheduleWindow {
+        ScheduleWindow {
+            days: days.map(|days| days.iter().map(|day| day.to_string()).collect()),
+            start: start.map(String::from),
+            end: end.map(String::from),
+        }
+    }
+
+    // 6 January 2025 is a Monday
+    fn monday_at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 1, 6)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn window_contains_its_start_but_not_its_end() {
+        let rush_hour = window(None, Some("16:00"), Some("19:00"));
+        assert!(!rush_hour.contains(monday_at(15, 59)));
+        assert!(rush_hour.contains(monday_at(16, 0)));
+        assert!(rush_hour.contains(monday_at(18, 59)));
+        assert!(!rush_hour.contains(monday_at(19, 0)));
+    }
+
+    #[test]
+    fn window_without_times_is_all_day() {
+        let any_time = window(None, None, None);
+        assert!(any_time.contains(monday_at(0, 0)));
+        assert!(any_time.contains(monday_at(23, 59)));
+        let evening = window(None, Some("20:00"), None);
+        assert!(!evening.contains(monday_at(19, 59)));
+        assert!(evening.contains(monday_at(23, 59)));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let night = window(None, Some("22:00"), Some("06:00"));
+        assert!(night.contains(monday_at(22, 0)));
+        assert!(night.contains(monday_at(0, 0)));
+        assert!(night.contains(monday_at(5, 59)));
+        assert!(!night.contains(monday_at(6, 0)));
+        assert!(!night.contains(monday_at(12, 0)));
+    }
+
+    #[test]
+    fn window_days_match_the_local_date() {
+        let weekends = window(Some(&["Sat", "Sun"]), Some("22:00"), Some("02:00"));
+        // Monday 01:00 is still part of Sunday night, but the day is Monday
+        assert!(!weekends.contains(monday_at(1, 0)));
+        assert!(!weekends.contains(monday_at(23, 0)));
+        let mondays = window(Some(&["Mon"]), None, None);
+        assert!(mondays.contains(monday_at(12, 0)));
+        assert!(!mondays.contains(monday_at(12, 0) + TimeDelta::days(1)));
+    }
+
+    #[test]
+    fn validate_drops_times_that_dont_parse() {
+        let mut window = window(Some(&["Mon", "Someday"]), Some("25:99"), Some("19:00"));
+        window.validate();
+        assert_eq!(window.start, None);
+        assert_eq!(window.end.as_deref(), Some("19:00"));
+        assert!(window.contains(monday_at(0, 0)));
+        assert!(!window.contains(monday_at(19, 0)));
+    }
+
+    fn list(values: &[&str]) -> Option<Vec<String>> {
+        Some(values.iter().map(|value| value.to_string()).collect())
+    }
+
+    #[test]
+    fn filters_without_lists_allow_everything() {
+        let filters = ArrivalFilters::default();
+        assert!(filters.allows("RD", "1", Some("A15"), false));
+        assert!(filters.allows("RD", "1", None, true));
+    }
+
+    #[test]
+    fn filters_include_and_exclude() {
+        let filters = ArrivalFilters {
+            include_lines: list(&["rd", "OR"]),
+            exclude_groups: list(&["2"]),
+            ..Default::default()
+        };
+        assert!(filters.allows("RD", "1", None, false));
+        assert!(filters.allows("OR", "1", None, false));
+        assert!(!filters.allows("SV", "1", None, false));
+        assert!(!filters.allows("RD", "2", None, false));
+        // An empty include list doesn't hide everything
+        let filters = ArrivalFilters {
+            include_lines: list(&[]),
+            ..Default::default()
+        };
+        assert!(filters.allows("SV", "1", None, false));
+    }
+
+    #[test]
+    fn filters_on_destination() {
+        let included = ArrivalFilters {
+            include_destinations: list(&["A15"]),
+            ..Default::default()
+        };
+        assert!(included.allows("RD", "1", Some("A15"), false));
+        assert!(!included.allows("RD", "1", Some("B11"), false));
+        // Trains without a destination code can't match an include list
+        assert!(!included.allows("RD", "1", None, false));
+        let excluded = ArrivalFilters {
+            exclude_destinations: list(&["A15"]),
+            ..Default::default()
+        };
+        assert!(!excluded.allows("RD", "1", Some("a15"), false));
+        assert!(excluded.allows("RD", "1", None, false));
+    }
+
+    #[test]
+    fn filters_hide_no_passenger_trains() {
+        let filters = ArrivalFilters {
+            hide_no_passenger: Some(true),
+            ..Default::default()
+        };
+        assert!(!filters.allows("RD", "1", None, true));
+        assert!(filters.allows("RD", "1", None, false));
+    }
+
+    fn apply(tree: &mut Value, event: &str, data: Value) {
+        apply_stream_event(tree, event, &data.to_string()).unwrap();
+    }
+
+    #[test]
+    fn put_replaces_the_value_at_its_path() {
+        let mut tree = Value::Null;
+        apply(
+            &mut tree,
+            "put",
+            json!({ "path": "/", "data": { "a": { "name": "clock" } } }),
+        );
+        apply(
+            &mut tree,
+            "put",
+            json!({ "path": "/a/name", "data": "weather" }),
+        );
+        apply(&mut tree, "put", json!({ "path": "/b/c", "data": 1 }));
+        assert_eq!(tree, json!({ "a": { "name": "weather" }, "b": { "c": 1 } }));
+    }
+
+    #[test]
+    fn put_of_null_deletes() {
+        let mut tree = json!({ "a": { "name": "clock" }, "b": 1 });
+        apply(&mut tree, "put", json!({ "path": "/a", "data": null }));
+        assert_eq!(tree, json!({ "b": 1 }));
+    }
+
+    #[test]
+    fn patch_puts_each_child() {
+        let mut tree = json!({ "a": { "name": "clock", "seconds": 5, "format": "12h" } });
+        apply(
+            &mut tree,
+            "patch",
+            json!({ "path": "/a", "data": { "seconds": 10, "format": null, "new": true } }),
+        );
+        assert_eq!(
+            tree,
+            json!({ "a": { "name": "clock", "seconds": 10, "new": true } })
+        );
+    }
+
+    #[test]
+    fn malformed_events_are_errors() {
+        let mut tree = json!({ "a": 1 });
+        assert!(apply_stream_event(&mut tree, "put", "not json").is_err());
+        assert!(apply_stream_event(&mut tree, "put", "{\"data\": 1}").is_err());
+        assert_eq!(tree, json!({ "a": 1 }));
+    }
+
+    fn snapshot(tree: Value) -> Option<WidgetSnapshot> {
+        Some(serde_json::from_value(tree).unwrap())
+    }
+
+    async fn was_edited(widgets_rx: &mut Receiver<Option<WidgetSnapshot>>, key: &str) -> bool {
+        let config = get_widget_config(&widgets_rx.borrow(), key);
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            wait_for_widget_edit(widgets_rx, key, &config),
+        )
+        .await
+        .is_ok()
+    }
+
+    #[tokio::test]
+    async fn edits_to_other_widgets_are_ignored() {
+        let tree = json!({ "a": { "name": "clock" }, "b": { "name": "bus" } });
+        let (widgets_tx, mut widgets_rx) = tokio::sync::watch::channel(snapshot(tree));
+        widgets_tx.send_modify(|snapshot| {
+            snapshot.as_mut().unwrap().insert(
+                String::from("b"),
+                json!({ "name": "bus", "stop_id": "1001" }),
+            );
+        });
+        assert!(!was_edited(&mut widgets_rx, "a").await);
+        widgets_tx.send_modify(|snapshot| {
+            snapshot
+                .as_mut()
+                .unwrap()
+                .insert(String::from("c"), json!({ "name": "weather" }));
+        });
+        assert!(!was_edited(&mut widgets_rx, "a").await);
+    }
+
+    #[tokio::test]
+    async fn edits_to_the_widget_wake_it() {
+        let tree = json!({ "a": { "name": "clock" }, "b": { "name": "bus" } });
+        let (widgets_tx, mut widgets_rx) = tokio::sync::watch::channel(snapshot(tree));
+        let config = get_widget_config(&widgets_rx.borrow(), "a");
+        let edit = tokio::spawn(async move {
+            wait_for_widget_edit(&mut widgets_rx, "a", &config).await;
+        });
+        widgets_tx.send_modify(|snapshot| {
+            snapshot.as_mut().unwrap().insert(
+                String::from("a"),
+                json!({ "name": "clock", "format": "24h" }),
+            );
+        });
+        tokio::time::timeout(Duration::from_millis(50), edit)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn removing_the_widget_wakes_it() {
+        let tree = json!({ "a": { "name": "clock" } });
+        let (widgets_tx, mut widgets_rx) = tokio::sync::watch::channel(snapshot(tree));
+        let config = get_widget_config(&widgets_rx.borrow(), "a");
+        widgets_tx.send_modify(|snapshot| {
+            snapshot.as_mut().unwrap().remove("a");
+        });
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            wait_for_widget_edit(&mut widgets_rx, "a", &config),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn set_at_path_replaces_values_that_arent_objects() {
+        let mut tree = json!({ "a": 1 });
+        set_at_path(&mut tree, &["a", "b"], json!(2));
+        assert_eq!(tree, json!({ "a": { "b": 2 } }));
+        set_at_path(&mut tree, &[], json!("root"));
+        assert_eq!(tree, json!("root"));
+    }
 }
@@ -0,0 +1,464 @@
+use std::{collections::HashMap, env, error::Error, fs, path::PathBuf, sync::LazyLock};
+
+use embedded_graphics::{
+    prelude::{DrawTarget, PixelColor, Point, Size},
+    primitives::Rectangle,
+    text::{
+        renderer::{CharacterStyle, TextMetrics, TextRenderer},
+        Baseline,
+    },
+    Pixel,
+};
+use log::{info, warn};
+
+// The fonts the original Python sign used, built into the binary. A file with the same name in
+// FONT_DIR replaces one without rebuilding, e.g. with a proportional font.
+pub static FONT_5X7: LazyLock<BdfFont> =
+    LazyLock::new(|| load_font("5x7.bdf", include_str!("../../pi/5x7.bdf")));
+pub static FONT_6X10: LazyLock<BdfFont> =
+    LazyLock::new(|| load_font("6x10.bdf", include_str!("../../pi/6x10.bdf")));
+pub static FONT_7X14: LazyLock<BdfFont> =
+    LazyLock::new(|| load_font("7x14.bdf", include_str!("../../pi/7x14.bdf")));
+
+fn load_font(file_name: &str, embedded: &str) -> BdfFont {
+    if let Ok(dir) = env::var("FONT_DIR") {
+        let path = PathBuf::from(dir).join(file_name);
+        if path.exists() {
+            match fs::read_to_string(&path)
+                .map_err(|err| err.into())
+                .and_then(|source| BdfFont::parse(&source))
+            {
+                Ok(font) => {
+                    info!(target: "fonts", "Loaded {}", path.display());
+                    return font;
+                }
+                Err(err) => {
+                    warn!(target: "fonts", "Could not load {}, using the built-in font: {}", path.display(), err)
+                }
+            }
+        }
+    }
+    BdfFont::parse(embedded).expect("Built-in font is invalid")
+}
+
+#[derive(Clone, Debug)]
+struct Glyph {
+    advance: i32, // DWIDTH, which differs between glyphs in a proportional font
+    size: Size,
+    offset: Point, // Of the bitmap's bottom left corner from the origin on the baseline, y up
+    bitmap: Vec<u8>, // Rows of bits, each padded to a whole byte
+}
+
+impl Glyph {
+    fn is_set(&self, x: u32, y: u32) -> bool {
+        let row_bytes = self.size.width.div_ceil(8);
+        let byte = self.bitmap[(y * row_bytes + x / 8) as usize];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+}
+
+// A bitmap font in the Glyph Bitmap Distribution Format, as used by rpi-rgb-led-matrix
+#[derive(Clone, Debug)]
+pub struct BdfFont {
+    glyphs: HashMap<char, Glyph>,
+    default_glyph: Option<char>, // Drawn for characters the font doesn't have
+    ascent: i32,
+    descent: i32,
+}
+
+impl BdfFont {
+    pub fn parse(source: &str) -> Result<BdfFont, Box<dyn Error + Send + Sync>> {
+        let mut glyphs = HashMap::new();
+        let mut bounding_box: Option<(Size, Point)> = None;
+        let mut ascent = None;
+        let mut descent = None;
+        let mut default_char = None;
+
+        let mut lines = source.lines().map(str::trim);
+        while let Some(line) = lines.next() {
+            let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "FONTBOUNDINGBOX" => bounding_box = Some(parse_bounding_box(args)?),
+                "FONT_ASCENT" => ascent = Some(args.trim().parse()?),
+                "FONT_DESCENT" => descent = Some(args.trim().parse()?),
+                "DEFAULT_CHAR" => default_char = Some(args.trim().parse::<u32>()?),
+                "STARTCHAR" => {
+                    let (encoding, glyph) = parse_glyph(&mut lines, bounding_box)?;
+                    if let Some(character) = encoding.and_then(char::from_u32) {
+                        glyphs.insert(character, glyph);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Fonts without the ascent and descent properties fill their bounding box
+        let (size, offset) = bounding_box.ok_or("BDF font has no FONTBOUNDINGBOX")?;
+        let font = BdfFont {
+            default_glyph: default_char
+                .and_then(char::from_u32)
+                .filter(|character| glyphs.contains_key(character))
+                .or(Some('?').filter(|character| glyphs.contains_key(character))),
+            glyphs,
+            ascent: ascent.unwrap_or(size.height as i32 + offset.y),
+            descent: descent.unwrap_or(-offset.y),
+        };
+        Ok(font)
+    }
+
+    fn get_glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs
+            .get(&character)
+            .or_else(|| self.glyphs.get(&self.default_glyph?))
+    }
+
+    // Whether the font has its own glyph for the character, rather than the default one
+    pub fn has_glyph(&self, character: char) -> bool {
+        self.glyphs.contains_key(&character)
+    }
+
+    pub fn get_char_width(&self, character: char) -> i32 {
+        self.get_glyph(character)
+            .map(|glyph| glyph.advance)
+            .unwrap_or(0)
+    }
+
+    // Width of a column of text, e.g. the minutes on the arrival board. Digits are the same
+    // width even in most proportional fonts.
+    pub fn get_column_width(&self) -> u32 {
+        self.get_glyph('0')
+            .map(|glyph| glyph.advance.max(0) as u32)
+            .unwrap_or(0)
+    }
+
+    pub fn get_line_height(&self) -> u32 {
+        (self.ascent + self.descent).max(0) as u32
+    }
+
+    // Rows of the line below the baseline
+    pub fn get_descent(&self) -> i32 {
+        self.descent
+    }
+
+    pub fn get_text_width(&self, text: &str) -> u32 {
+        text.chars()
+            .map(|character| self.get_char_width(character))
+            .sum::<i32>()
+            .max(0) as u32
+    }
+}
+
+fn parse_numbers(args: &str) -> Result<Vec<i32>, Box<dyn Error + Send + Sync>> {
+    Ok(args
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()?)
+}
+
+fn parse_bounding_box(args: &str) -> Result<(Size, Point), Box<dyn Error + Send + Sync>> {
+    match parse_numbers(args)?[..] {
+        [width, height, x, y] if width >= 0 && height >= 0 => {
+            Ok((Size::new(width as u32, height as u32), Point::new(x, y)))
+        }
+        _ => Err(format!("Invalid bounding box {}", args).into()),
+    }
+}
+
+// Reads one glyph, up to and including ENDCHAR. Glyphs without a Unicode encoding come back as None.
+fn parse_glyph<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+    font_bounding_box: Option<(Size, Point)>,
+) -> Result<(Option<u32>, Glyph), Box<dyn Error + Send + Sync>> {
+    let mut encoding = None;
+    let mut advance = None;
+    let mut bounding_box = font_bounding_box;
+    loop {
+        let line = lines.next().ok_or("BDF glyph has no ENDCHAR")?;
+        let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+        match keyword {
+            "ENCODING" => {
+                encoding = parse_numbers(args)?
+                    .first()
+                    .and_then(|&encoding| u32::try_from(encoding).ok())
+            }
+            "DWIDTH" => advance = parse_numbers(args)?.first().copied(),
+            "BBX" => bounding_box = Some(parse_bounding_box(args)?),
+            "BITMAP" => {
+                let (size, offset) = bounding_box.ok_or("BDF glyph has no BBX")?;
+                let row_bytes = size.width.div_ceil(8) as usize;
+                let mut bitmap = Vec::with_capacity(row_bytes * size.height as usize);
+                for _ in 0..size.height {
+                    let row = lines.next().ok_or("BDF bitmap is too short")?;
+                    for byte in 0..row_bytes {
+                        let hex = row.get(byte * 2..byte * 2 + 2).unwrap_or("00");
+                        bitmap.push(u8::from_str_radix(hex, 16)?);
+                    }
+                }
+                let glyph = Glyph {
+                    advance: advance.unwrap_or(size.width as i32),
+                    size,
+                    offset,
+                    bitmap,
+                };
+                return match lines.next() {
+                    Some("ENDCHAR") => Ok((encoding, glyph)),
+                    _ => Err("BDF bitmap is too long".into()),
+                };
+            }
+            "ENDCHAR" => return Err("BDF glyph has no BITMAP".into()),
+            _ => {}
+        }
+    }
+}
+
+// Draws text in a BDF font, like MonoTextStyle does for the built-in fonts. Works with Text and
+// TextBox alike.
+#[derive(Clone, Copy, Debug)]
+pub struct BdfTextStyle<'a, C> {
+    font: &'a BdfFont,
+    text_color: Option<C>,
+    background_color: Option<C>,
+}
+
+impl<'a, C: PixelColor> BdfTextStyle<'a, C> {
+    pub fn new(font: &'a BdfFont, text_color: C) -> Self {
+        BdfTextStyle {
+            font,
+            text_color: Some(text_color),
+            background_color: None,
+        }
+    }
+
+    pub fn get_font(&self) -> &'a BdfFont {
+        self.font
+    }
+
+    fn get_baseline_y(&self, position: Point, baseline: Baseline) -> i32 {
+        let height = self.font.get_line_height() as i32;
+        let top_to_baseline = self.font.ascent - 1;
+        match baseline {
+            Baseline::Top => position.y + top_to_baseline,
+            Baseline::Bottom => position.y - (height - 1) + top_to_baseline,
+            Baseline::Middle => position.y - (height - 1) / 2 + top_to_baseline,
+            Baseline::Alphabetic => position.y,
+        }
+    }
+
+    // The whole height of the line, from where the text starts to where the next text would
+    fn get_line_box(&self, position: Point, baseline: Baseline, width: u32) -> Rectangle {
+        let top = self.get_baseline_y(position, baseline) - (self.font.ascent - 1);
+        Rectangle::new(
+            Point::new(position.x, top),
+            Size::new(width, self.font.get_line_height()),
+        )
+    }
+}
+
+impl<C: PixelColor> TextRenderer for BdfTextStyle<'_, C> {
+    type Color = C;
+
+    fn draw_string<D>(
+        &self,
+        text: &str,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let baseline_y = self.get_baseline_y(position, baseline);
+        let mut x = position.x;
+        for character in text.chars() {
+            let Some(glyph) = self.font.get_glyph(character) else {
+                continue;
+            };
+            if let Some(background_color) = self.background_color {
+                let area = self.get_line_box(
+                    Point::new(x, position.y),
+                    baseline,
+                    glyph.advance.max(0) as u32,
+                );
+                target.fill_solid(&area, background_color)?;
+            }
+            if let Some(text_color) = self.text_color {
+                // Bitmap rows go top to bottom, the glyph's offset goes up from the baseline
+                let top = baseline_y - (glyph.offset.y + glyph.size.height as i32 - 1);
+                let left = x + glyph.offset.x;
+                let pixels = (0..glyph.size.height)
+                    .flat_map(|y| (0..glyph.size.width).map(move |x| (x, y)))
+                    .filter(|&(x, y)| glyph.is_set(x, y))
+                    .map(|(x, y)| Pixel(Point::new(left + x as i32, top + y as i32), text_color));
+                target.draw_iter(pixels)?;
+            }
+            x += glyph.advance;
+        }
+        Ok(Point::new(x, position.y))
+    }
+
+    fn draw_whitespace<D>(
+        &self,
+        width: u32,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        if let Some(background_color) = self.background_color {
+            target.fill_solid(
+                &self.get_line_box(position, baseline, width),
+                background_color,
+            )?;
+        }
+        Ok(position + Point::new(width as i32, 0))
+    }
+
+    fn measure_string(&self, text: &str, position: Point, baseline: Baseline) -> TextMetrics {
+        let width = self.font.get_text_width(text);
+        TextMetrics {
+            bounding_box: self.get_line_box(position, baseline, width),
+            next_position: position + Point::new(width as i32, 0),
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.font.get_line_height()
+    }
+}
+
+impl<C: PixelColor> CharacterStyle for BdfTextStyle<'_, C> {
+    type Color = C;
+
+    fn set_text_color(&mut self, text_color: Option<C>) {
+        self.text_color = text_color;
+    }
+
+    fn set_background_color(&mut self, background_color: Option<C>) {
+        self.background_color = background_color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A proportional font with a narrow "i", a wide "W" and a box as the default character
+    const FONT: &str = "STARTFONT 2.1
+FONTBOUNDINGBOX 5 7 0 -1
+STARTPROPERTIES 3
+FONT_ASCENT 6
+FONT_DESCENT 1
+DEFAULT_CHAR 127
+ENDPROPERTIES
+CHARS 3
+STARTCHAR i
+ENCODING 105
+DWIDTH 2 0
+BBX 1 3 0 0
+BITMAP
+80
+80
+80
+ENDCHAR
+STARTCHAR W
+ENCODING 87
+DWIDTH 6 0
+BBX 5 2 0 0
+BITMAP
+A8
+50
+ENDCHAR
+STARTCHAR box
+ENCODING 127
+DWIDTH 4 0
+BITMAP
+F0
+90
+90
+90
+90
+90
+F0
+ENDCHAR
+ENDFONT
+";
+
+    fn parse_error(source: &str) -> String {
+        BdfFont::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_metrics() {
+        let font = BdfFont::parse(FONT).unwrap();
+        assert_eq!(font.get_line_height(), 7);
+        assert_eq!(font.get_descent(), 1);
+        let box_glyph = font.get_glyph('\u{7f}').unwrap();
+        assert_eq!(box_glyph.size, Size::new(5, 7));
+        assert!(box_glyph.is_set(3, 1) && !box_glyph.is_set(1, 1));
+    }
+
+    #[test]
+    fn proportional_widths_come_from_dwidth() {
+        let font = BdfFont::parse(FONT).unwrap();
+        assert_eq!(font.get_char_width('i'), 2);
+        assert_eq!(font.get_char_width('W'), 6);
+        assert_eq!(font.get_text_width("iWi"), 10);
+    }
+
+    #[test]
+    fn missing_characters_use_the_default_char() {
+        let font = BdfFont::parse(FONT).unwrap();
+        assert!(!font.has_glyph('x'));
+        assert_eq!(font.get_char_width('x'), 4);
+        // Without a DEFAULT_CHAR, and no "?" either, missing characters take no space
+        let font = BdfFont::parse(&FONT.replace("DEFAULT_CHAR 127\n", "")).unwrap();
+        assert_eq!(font.get_char_width('x'), 0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_error("STARTFONT 2.1\nENDFONT\n"),
+            "BDF font has no FONTBOUNDINGBOX"
+        );
+        // The box glyph has no BBX of its own, so it needs the font's
+        assert_eq!(
+            parse_error(&FONT.replace("FONTBOUNDINGBOX 5 7 0 -1\n", "")),
+            "BDF glyph has no BBX"
+        );
+        assert_eq!(
+            parse_error(&FONT.replace("FONTBOUNDINGBOX 5 7 0 -1", "FONTBOUNDINGBOX 5 7")),
+            "Invalid bounding box 5 7"
+        );
+        assert_eq!(
+            parse_error(
+                &FONT.replace("BBX 1 3 0 0\nBITMAP\n80\n", "BBX 1 3 0 0\nBITMAP\n80\n80\n")
+            ),
+            "BDF bitmap is too long"
+        );
+        let truncated = |end: &str| FONT[..FONT.find(end).unwrap() + end.len()].to_string();
+        assert_eq!(
+            parse_error(&truncated("ENCODING 87\n")),
+            "BDF glyph has no ENDCHAR"
+        );
+        assert_eq!(
+            parse_error(&truncated("BITMAP\nA8\n")),
+            "BDF bitmap is too short"
+        );
+        assert_eq!(
+            parse_error(&FONT.replace("BITMAP\nA8\n50\n", "")),
+            "BDF glyph has no BITMAP"
+        );
+        assert!(BdfFont::parse(&FONT.replace("DWIDTH 2 0", "DWIDTH two 0")).is_err());
+        assert!(BdfFont::parse(&FONT.replace("80\n80\n80", "80\nZZ\n80")).is_err());
+    }
+
+    #[test]
+    fn built_in_fonts_parse() {
+        for font in [&FONT_5X7, &FONT_6X10, &FONT_7X14] {
+            assert!(font.has_glyph('A') && font.get_column_width() > 0);
+        }
+    }
+}
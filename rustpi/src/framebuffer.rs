@@ -0,0 +1,104 @@
+use std::{convert::Infallible, error::Error, fs::File, io::BufWriter, path::Path};
+
+#[cfg(test)]
+use std::io::BufReader;
+
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, OriginDimensions, Point, RgbColor, Size},
+    Pixel,
+};
+
+// Off-screen Rgb888 canvas, used by the headless backend and for rendering in tests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameBuffer {
+    size: Size,
+    pixels: Vec<Rgb888>,
+}
+
+impl FrameBuffer {
+    pub fn new(size: Size) -> Self {
+        FrameBuffer {
+            size,
+            pixels: vec![Rgb888::BLACK; (size.width * size.height) as usize],
+        }
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        if point.x < 0
+            || point.y < 0
+            || point.x as u32 >= self.size.width
+            || point.y as u32 >= self.size.height
+        {
+            return None;
+        }
+        Some((point.y as u32 * self.size.width + point.x as u32) as usize)
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|pixel| [pixel.r(), pixel.g(), pixel.b()])
+            .collect();
+        encoder.write_header()?.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl FrameBuffer {
+    pub fn load_png(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight {
+            return Err(format!("{} is not an 8-bit RGB PNG", path.display()).into());
+        }
+        Ok(FrameBuffer {
+            size: Size::new(info.width, info.height),
+            pixels: data[..info.buffer_size()]
+                .chunks_exact(3)
+                .map(|rgb| Rgb888::new(rgb[0], rgb[1], rgb[2]))
+                .collect(),
+        })
+    }
+
+    pub fn get_pixel(&self, point: Point) -> Option<Rgb888> {
+        self.index(point).map(|i| self.pixels[i])
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some(i) = self.index(point) {
+                self.pixels[i] = color;
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(color);
+        Ok(())
+    }
+}
@@ -1,22 +1,156 @@
 #[cfg(feature = "rpi")]
 use rpi_led_panel::{RGBMatrixConfig, RGBMatrix, Canvas, HardwareMapping, NamedPixelMapperType};
 
-use std::fmt::Debug;
+use std::{env, fmt::Debug, str::FromStr};
+
+use log::warn;
 
 #[cfg(feature = "simulator")]
 use embedded_graphics_simulator::{OutputSettingsBuilder, SimulatorEvent, Window, SimulatorDisplay};
 #[cfg(feature = "simulator")]
 use embedded_graphics::prelude::RgbColor;
 
-use embedded_graphics::{pixelcolor::Rgb888, prelude::{DrawTarget, Size}};
+#[cfg(feature = "headless")]
+use std::{fs, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+#[cfg(feature = "headless")]
+use log::{error, info};
+#[cfg(feature = "headless")]
+use crate::framebuffer::FrameBuffer;
+#[cfg(feature = "headless")]
+use embedded_graphics::prelude::RgbColor;
+
+use embedded_graphics::{pixelcolor::Rgb888, prelude::{DrawTarget, Size}, primitives::Rectangle, geometry::Dimensions, Pixel};
+
+use crate::brightness::Brightness;
+
+// The original sign: a chain of four 64x32 panels folded into 128x64.
+// The simulator and headless builds are sized with SCREEN_WIDTH and SCREEN_HEIGHT instead.
+#[cfg(any(feature = "simulator", feature = "headless", test))]
+pub const DEFAULT_SCREEN_WIDTH: u32 = 64 * 2;
+#[cfg(any(feature = "simulator", feature = "headless", test))]
+pub const DEFAULT_SCREEN_HEIGHT: u32 = 32 * 2;
 
+// The canvas each build draws on, for code that can't be generic over DrawTarget (e.g. trait objects)
+#[cfg(feature = "rpi")]
+pub type ScreenCanvas = DimmedCanvas<Canvas>;
 #[cfg(feature = "simulator")]
-use crate::widgets::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub type ScreenCanvas = DimmedCanvas<SimulatorDisplay<Rgb888>>;
+#[cfg(feature = "headless")]
+pub type ScreenCanvas = DimmedCanvas<FrameBuffer>;
+
+// Passes everything drawn on to the screen, with the current brightness applied to every color
+pub struct DimmedCanvas<D> {
+    inner: D,
+    brightness: Brightness,
+}
+
+impl<D> DimmedCanvas<D> {
+    pub fn new(inner: D) -> Self {
+        DimmedCanvas { inner, brightness: Brightness::FULL }
+    }
+
+    pub fn set_brightness(&mut self, brightness: Brightness) {
+        self.brightness = brightness;
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Dimensions> Dimensions for DimmedCanvas<D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.inner.bounding_box()
+    }
+}
+
+impl<D: DrawTarget<Color = Rgb888>> DrawTarget for DimmedCanvas<D> {
+    type Color = Rgb888;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Rgb888>>,
+    {
+        let brightness = self.brightness;
+        self.inner.draw_iter(pixels.into_iter().map(|Pixel(point, color)| Pixel(point, brightness.apply(color))))
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Rgb888>,
+    {
+        let brightness = self.brightness;
+        self.inner.fill_contiguous(area, colors.into_iter().map(|color| brightness.apply(color)))
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Rgb888) -> Result<(), Self::Error> {
+        self.inner.fill_solid(area, self.brightness.apply(color))
+    }
+
+    fn clear(&mut self, color: Rgb888) -> Result<(), Self::Error> {
+        self.inner.clear(self.brightness.apply(color))
+    }
+}
 
 // Change depending on your monitor resolution.
 #[cfg(feature = "simulator")]
 const WINDOW_SCALING: u32 = 8;
 
+// Unset or unparsable variables keep the default
+fn get_env_or<T: FromStr>(name: &str, default: T) -> T {
+    get_env_optional(name).unwrap_or(default)
+}
+
+fn get_env_optional<T: FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        warn!(target: "led", "Could not parse {}={}, using the default", name, value);
+    }
+    parsed
+}
+
+#[cfg(any(feature = "simulator", feature = "headless"))]
+fn get_screen_size() -> Size {
+    Size::new(get_env_or("SCREEN_WIDTH", DEFAULT_SCREEN_WIDTH), get_env_or("SCREEN_HEIGHT", DEFAULT_SCREEN_HEIGHT))
+}
+
+// Every setting of the panels can be overridden with a PANEL_* variable. The defaults are the
+// original sign's, and pixel mappers are separated by semicolons, e.g. "U-mapper;Rotate:90".
+#[cfg(feature = "rpi")]
+fn get_matrix_config() -> RGBMatrixConfig {
+    let mut config = RGBMatrixConfig::default();
+    config.hardware_mapping = get_env_or("PANEL_HARDWARE_MAPPING", HardwareMapping::adafruit_hat_pwm());
+    config.rows = get_env_or("PANEL_ROWS", 32);
+    config.cols = get_env_or("PANEL_COLS", 64);
+    config.chain_length = get_env_or("PANEL_CHAIN_LENGTH", 4);
+    config.parallel = get_env_or("PANEL_PARALLEL", config.parallel);
+    config.pixelmapper = match env::var("PANEL_PIXEL_MAPPER") {
+        Ok(mappers) => mappers.split(';').filter(|mapper| !mapper.is_empty()).filter_map(|mapper| {
+            let parsed = mapper.parse().ok();
+            if parsed.is_none() {
+                warn!(target: "led", "Unknown pixel mapper {}, skipping it", mapper);
+            }
+            parsed
+        }).collect(),
+        Err(_) => vec![NamedPixelMapperType::UMapper],
+    };
+    config.refresh_rate = get_env_or("PANEL_REFRESH_RATE", 120);
+    config.pwm_lsb_nanoseconds = get_env_or("PANEL_PWM_LSB_NANOSECONDS", 50);
+    config.pwm_bits = get_env_or("PANEL_PWM_BITS", 7);
+    config.dither_bits = get_env_or("PANEL_DITHER_BITS", config.dither_bits);
+    config.interlaced = get_env_or("PANEL_INTERLACED", config.interlaced);
+    config.led_sequence = get_env_or("PANEL_LED_SEQUENCE", config.led_sequence);
+    config.row_setter = get_env_or("PANEL_ROW_SETTER", config.row_setter);
+    config.slowdown = get_env_optional("PANEL_SLOWDOWN").or(config.slowdown);
+    config.multiplexing = get_env_optional("PANEL_MULTIPLEXING").or(config.multiplexing);
+    config.panel_type = get_env_optional("PANEL_TYPE").or(config.panel_type);
+    config.pi_chip = get_env_optional("PANEL_PI_CHIP").or(config.pi_chip);
+    config
+}
+
 
 pub trait DrawableScreen<D>
 where
@@ -33,60 +167,50 @@ where
 #[cfg(feature = "rpi")]
 pub struct ScreenManager {
     matrix: RGBMatrix,
-    canvas: Box<Canvas>
+    canvas: ScreenCanvas
 }
 
 #[cfg(feature = "rpi")]
-impl DrawableScreen<Canvas> for ScreenManager {
+impl DrawableScreen<ScreenCanvas> for ScreenManager {
 
     fn clear(&mut self) {
-        self.canvas.fill(0, 0, 0);
+        self.canvas.inner.fill(0, 0, 0);
     }
 
     fn run_updates_should_exit(&mut self) -> bool {
-        self.canvas = self.matrix.update_on_vsync(self.canvas.clone());
+        self.canvas.inner = *self.matrix.update_on_vsync(Box::new(self.canvas.inner.clone()));
 
         false
     }
 
     fn init() -> Self {
-        let mut config = RGBMatrixConfig::default();
-        config.hardware_mapping = HardwareMapping::adafruit_hat_pwm();
-        config.rows = 32;
-        config.cols = 64;
-        config.chain_length = 4;
-        config.pixelmapper = vec![NamedPixelMapperType::UMapper];
-        config.refresh_rate = 120;
-        config.pwm_lsb_nanoseconds = 50;
-        config.pwm_bits = 7;
-
-        let (matrix, canvas) = RGBMatrix::new(config, 0).expect("Matrix initialization failed");
+        let (matrix, canvas) = RGBMatrix::new(get_matrix_config(), 0).expect("Matrix initialization failed");
         ScreenManager {
             matrix: matrix,
-            canvas: canvas
+            canvas: DimmedCanvas::new(*canvas)
         }
     }
 
-    fn get_canvas(&mut self) -> &mut Canvas {
-        self.canvas.as_mut()
+    fn get_canvas(&mut self) -> &mut ScreenCanvas {
+        &mut self.canvas
     }
 }
 
 #[cfg(feature = "simulator")]
 pub struct ScreenManager {
     window: Window,
-    canvas: SimulatorDisplay<Rgb888>
+    canvas: ScreenCanvas
 }
 
 #[cfg(feature = "simulator")]
-impl DrawableScreen<SimulatorDisplay<Rgb888>> for ScreenManager {
+impl DrawableScreen<ScreenCanvas> for ScreenManager {
 
     fn clear(&mut self) {
         self.canvas.clear(Rgb888::BLACK).unwrap();
     }
 
     fn run_updates_should_exit(&mut self) -> bool {
-        self.window.update(&self.canvas);
+        self.window.update(&self.canvas.inner);
 
         self.window.events().any(|e| e == SimulatorEvent::Quit)
     }
@@ -94,12 +218,84 @@ impl DrawableScreen<SimulatorDisplay<Rgb888>> for ScreenManager {
     fn init() -> Self {
         let output_settings = OutputSettingsBuilder::new().scale(WINDOW_SCALING).build();
         ScreenManager {
-            canvas: SimulatorDisplay::<Rgb888>::new(Size::new(SCREEN_WIDTH, SCREEN_HEIGHT)),
+            canvas: DimmedCanvas::new(SimulatorDisplay::<Rgb888>::new(get_screen_size())),
             window: Window::new("Metro Sign Simulator", &output_settings)
         }
     }
 
-    fn get_canvas(&mut self) -> &mut SimulatorDisplay<Rgb888> {
+    fn get_canvas(&mut self) -> &mut ScreenCanvas {
+        &mut self.canvas
+    }
+}
+
+// Renders into memory only. Frames are written as PNGs every HEADLESS_DUMP_EVERY frames,
+// or once after receiving SIGUSR1.
+#[cfg(feature = "headless")]
+pub struct ScreenManager {
+    canvas: ScreenCanvas,
+    frame: u64,
+    dump_dir: PathBuf,
+    dump_every: Option<u64>,
+    max_frames: Option<u64>,
+    dump_requested: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "headless")]
+impl ScreenManager {
+    pub fn dump_frame(&self) {
+        let path = self.dump_dir.join(format!("frame-{:06}.png", self.frame));
+        match self.canvas.inner.save_png(&path) {
+            Ok(_) => info!(target: "headless", "Wrote {}", path.display()),
+            Err(err) => error!(target: "headless", "Could not write {}: {}", path.display(), err),
+        }
+    }
+}
+
+#[cfg(feature = "headless")]
+impl DrawableScreen<ScreenCanvas> for ScreenManager {
+
+    fn clear(&mut self) {
+        self.canvas.clear(Rgb888::BLACK).unwrap();
+    }
+
+    fn run_updates_should_exit(&mut self) -> bool {
+        let periodic = self.dump_every.is_some_and(|n| self.frame.is_multiple_of(n));
+        if periodic || self.dump_requested.swap(false, Ordering::Relaxed) {
+            self.dump_frame();
+        }
+        self.frame += 1;
+
+        self.max_frames.is_some_and(|max| self.frame >= max)
+    }
+
+    fn init() -> Self {
+        let dump_dir = PathBuf::from(env::var("HEADLESS_DUMP_DIR").unwrap_or(String::from("frames")));
+        fs::create_dir_all(&dump_dir).expect("Could not create frame dump directory");
+
+        let dump_requested = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        {
+            let dump_requested = dump_requested.clone();
+            tokio::spawn(async move {
+                use tokio::signal::unix::{signal, SignalKind};
+                let mut signals = signal(SignalKind::user_defined1()).expect("Could not listen for SIGUSR1");
+                while signals.recv().await.is_some() {
+                    dump_requested.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+
+        ScreenManager {
+            canvas: DimmedCanvas::new(FrameBuffer::new(get_screen_size())),
+            frame: 0,
+            dump_dir,
+            dump_every: env::var("HEADLESS_DUMP_EVERY").ok().and_then(|n| n.parse().ok()).filter(|&n| n > 0),
+            max_frames: env::var("HEADLESS_MAX_FRAMES").ok().and_then(|n| n.parse().ok()),
+            dump_requested,
+        }
+    }
+
+    fn get_canvas(&mut self) -> &mut ScreenCanvas {
         &mut self.canvas
     }
 }
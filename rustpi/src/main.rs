@@ -1,21 +1,27 @@
 #[cfg(all(feature = "rpi", feature = "simulator"))]
 compile_error!("feature \"rpi\" and feature \"simulator\" cannot be enabled at the same time");
+#[cfg(all(feature = "headless", any(feature = "rpi", feature = "simulator")))]
+compile_error!("feature \"headless\" cannot be enabled together with \"rpi\" or \"simulator\"");
 
+mod brightness;
 mod firebase;
+mod fonts;
+#[cfg(any(feature = "headless", test))]
+mod framebuffer;
 mod led;
 mod widgets;
+mod wmata;
 
+use brightness::BrightnessControl;
 use chrono::Utc;
 use dotenv::dotenv;
+use firebase::{spawn_widget_list_task, spawn_widget_stream};
 use led::{ DrawableScreen, ScreenManager};
 use tokio::sync::watch;
 use std::time::Duration;
 use widgets::{
-    alerts::{render_alert_display, spawn_alert_update_task, AlertMode, AlertState},
-    arrival::{
-        render_arrival_display, spawn_arrival_update_task,
-        ArrivalState, SimpleArrivalDisplayable,
-    },
+    arrival::{render_arrival_display, ArrivalFrame, SimpleArrivalDisplayable},
+    registry::WidgetRegistry,
 };
 
 
@@ -43,43 +49,43 @@ async fn main() {
 
     let mut manager = ScreenManager::init();
 
-    let mut loading_message: Vec<SimpleArrivalDisplayable> = Vec::new();
-    loading_message.push(SimpleArrivalDisplayable::loading());
-    let (arrival_tx, mut arrival_rx) = watch::channel(ArrivalState {
-        messages: loading_message,
-        last_update: Utc::now(),
-    });
-    spawn_arrival_update_task(arrival_tx);
+    let (widgets_tx, widgets_rx) = watch::channel(None);
+    spawn_widget_stream(widgets_tx);
+    let (widget_list_tx, mut widget_list_rx) = watch::channel(None);
+    spawn_widget_list_task(widgets_rx.clone(), widget_list_tx);
 
-    let (alert_tx, mut alert_rx) = watch::channel(AlertState::blank());
-    spawn_alert_update_task(alert_tx);
+    // One instance of each widget configured in Firebase, each with its own update task
+    let mut registry = WidgetRegistry::with_builtin_widgets(widgets_rx.clone());
+    let mut brightness = BrightnessControl::new(widgets_rx);
 
     'running: loop {
         manager.clear();
 
-        let mut messages: Vec<SimpleArrivalDisplayable> = Vec::new();
-        let mut alert_state: AlertState = AlertState::blank();
-
-        let arrival_res = arrival_rx.has_changed();
-        if arrival_res.is_ok() {
-            messages = arrival_rx.borrow_and_update().messages.clone();
-        }
-
-        let alert_res = alert_rx.has_changed();
-        if alert_res.is_ok() {
-            alert_state = alert_rx.borrow_and_update().clone();
+        let widget_list_res = widget_list_rx.has_changed();
+        if widget_list_res.is_ok_and(|changed| changed) {
+            if let Some(widget_list) = widget_list_rx.borrow_and_update().as_ref() {
+                registry.sync(widget_list);
+                brightness.sync(widget_list);
+            }
         }
 
-        if alert_state.mode != AlertMode::Hidden {
-            render_alert_display(alert_state, manager.get_canvas());
-        } else {
-            render_arrival_display(messages, manager.get_canvas());
+        let now = Utc::now();
+        manager.get_canvas().set_brightness(brightness.get_brightness(now));
+
+        // Until we know which widgets are configured, show the arrival board as loading
+        let rendered = registry.render(now, manager.get_canvas());
+        if !rendered && widget_list_rx.borrow().is_none() {
+            render_arrival_display(
+                vec![SimpleArrivalDisplayable::loading()],
+                ArrivalFrame::default(),
+                manager.get_canvas(),
+            );
         }
         if manager.run_updates_should_exit() {
             break 'running;
         }
 
-        #[cfg(feature = "simulator")]
+        #[cfg(any(feature = "simulator", feature = "headless"))]
         tokio::time::sleep(Duration::from_millis(50)).await;
     }
 }
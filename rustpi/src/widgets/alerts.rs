@@ -1,5 +1,6 @@
-use std::{fmt::Debug, time::Duration};
+use std::{collections::HashSet, error::Error, fmt::Debug, time::Duration};
 
+use chrono::{DateTime, Utc};
 use embedded_graphics::{
     mono_font::{
         ascii::{FONT_6X10, FONT_7X14_BOLD},
@@ -14,14 +15,26 @@ use embedded_text::{
     style::{HeightMode, TextBoxStyleBuilder},
     TextBox,
 };
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use rand::seq::IteratorRandom;
-use tokio::{spawn, sync::watch::Sender, task::JoinHandle};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
 
-use crate::firebase::{AlertWidget, LoadableWidget};
+use crate::{
+    firebase::{get_latest_widget, AlertWidget, LoadableWidget, WidgetSnapshot},
+    led::ScreenCanvas,
+    wmata::WmataClient,
+};
 use embedded_graphics::Drawable;
 
-use super::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use super::{
+    get_screen_size,
+    registry::{Visibility, Widget, PRIORITY_ALERT},
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AlertMode {
@@ -49,42 +62,122 @@ impl AlertState {
     }
 }
 
-pub fn spawn_alert_update_task(state_tx: Sender<AlertState>) -> JoinHandle<()> {
+#[cfg(test)]
+impl AlertState {
+    pub fn fixture(mode: AlertMode, message: &str, scroll_index: u32) -> Self {
+        AlertState {
+            mode,
+            currently_shown_message: message.to_string(),
+            scroll_index,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IncidentApiReturn {
+    #[serde(rename(deserialize = "Incidents"))]
+    incidents: Vec<Incident>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Incident {
+    #[serde(rename(deserialize = "IncidentID"))]
+    incident_id: String,
+    #[serde(rename(deserialize = "Description"))]
+    description: String,
+    #[serde(rename(deserialize = "LinesAffected"))]
+    lines_affected: String, // Semicolon separated, e.g. "RD; OR;"
+    #[serde(rename(deserialize = "DateUpdated"))]
+    date_updated: String,
+}
+
+impl Incident {
+    fn get_lines(&self) -> Vec<&str> {
+        self.lines_affected
+            .split(';')
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    // An incident is shown again if WMATA updates it
+    fn get_key(&self) -> String {
+        format!("{}@{}", self.incident_id, self.date_updated)
+    }
+}
+
+const INCIDENTS_PATH: &str = "/Incidents.svc/json/Incidents";
+
+async fn get_incidents(
+    client: &WmataClient,
+    widget: &AlertWidget,
+) -> Result<Vec<Incident>, Box<dyn Error + Send + Sync>> {
+    let api_return: IncidentApiReturn = client.get(INCIDENTS_PATH, &[]).await?;
+    Ok(api_return
+        .incidents
+        .into_iter()
+        .filter(|incident| widget.wants_incident_lines(&incident.get_lines()))
+        .collect())
+}
+
+pub fn spawn_alert_update_task(
+    client: WmataClient,
+    key: String,
+    mut widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<AlertState>,
+) -> JoinHandle<()> {
     spawn(async move {
+        let mut shown_incidents: HashSet<String> = HashSet::new();
+        tokio::time::sleep(Duration::from_secs(10)).await;
         loop {
-            tokio::time::sleep(Duration::from_secs(10)).await;
             debug!(target: "alert_state_update", "Loading new state...");
 
-            let new_state = AlertWidget::load().await;
-            debug!(target: "alert_state_update", "{:?}", new_state.alerts);
-            info!(target: "alert_state_update", "New state loaded. Sending to main thread.");
-            let intro_a = AlertState {
-                mode: AlertMode::IntroA,
-                currently_shown_message: new_state
-                    .alerts
-                    .iter()
-                    .choose(&mut rand::rng())
-                    .unwrap()
-                    .message
-                    .to_string(),
-                scroll_index: 0,
-            };
-            let intro_b = AlertState {
-                mode: AlertMode::IntroB,
-                currently_shown_message: intro_a.currently_shown_message.clone(),
-                scroll_index: 0,
+            let snapshot = widgets_rx.borrow_and_update().clone();
+            let widget = get_latest_widget::<AlertWidget>(snapshot, &key).await;
+            let messages = match &widget {
+                Some(widget) => widget.get_messages(),
+                None => {
+                    warn!(target: "alert_state_update", "Alert widget is missing, showing it as disabled.");
+                    Vec::new()
+                }
             };
-            let mut message_a = AlertState {
-                mode: AlertMode::MessageA,
-                currently_shown_message: intro_a.currently_shown_message.clone(),
-                scroll_index: 0,
+            let incidents = match &widget {
+                Some(widget) if widget.should_show_incidents() => {
+                    get_incidents(&client, widget).await.unwrap_or_else(|err| {
+                        error!(target: "alert_state_update", "Could not load incidents: {}", err);
+                        Vec::new()
+                    })
+                }
+                _ => Vec::new(),
             };
-            let mut message_b = AlertState {
-                mode: AlertMode::MessageB,
-                currently_shown_message: intro_a.currently_shown_message.clone(),
-                scroll_index: 0,
+            debug!(target: "alert_state_update", "{:?} {:?}", messages, incidents);
+            info!(target: "alert_state_update", "New state loaded. Sending to main thread.");
+
+            let chosen = match choose_next_incident(&incidents, &messages, &mut shown_incidents) {
+                Some(incident) => Some(incident.description.clone()),
+                None => messages.iter().choose(&mut rand::rng()).cloned(),
             };
-            if new_state.alerts.len() > 0 {
+            if let Some(message) = chosen {
+                let intro_a = AlertState {
+                    mode: AlertMode::IntroA,
+                    currently_shown_message: message,
+                    scroll_index: 0,
+                };
+                let intro_b = AlertState {
+                    mode: AlertMode::IntroB,
+                    currently_shown_message: intro_a.currently_shown_message.clone(),
+                    scroll_index: 0,
+                };
+                let mut message_a = AlertState {
+                    mode: AlertMode::MessageA,
+                    currently_shown_message: intro_a.currently_shown_message.clone(),
+                    scroll_index: 0,
+                };
+                let mut message_b = AlertState {
+                    mode: AlertMode::MessageB,
+                    currently_shown_message: intro_a.currently_shown_message.clone(),
+                    scroll_index: 0,
+                };
                 // Show intro
                 for _ in 1..3 {
                     state_tx.send(intro_a.clone()).unwrap();
@@ -112,21 +205,75 @@ pub fn spawn_alert_update_task(state_tx: Sender<AlertState>) -> JoinHandle<()> {
                 }
             }
             state_tx.send(AlertState::blank()).unwrap();
-            tokio::time::sleep(Duration::from_secs(rand::random_range(60..300))).await;
+            wait_for_next_alert(
+                &mut widgets_rx,
+                &key,
+                &messages,
+                Duration::from_secs(rand::random_range(60..300)),
+            )
+            .await;
         }
     })
 }
 
+// Each incident is shown once, ahead of the hand-entered alerts. Incidents repeating a
+// hand-entered alert or an earlier incident are skipped.
+fn choose_next_incident<'a>(
+    incidents: &'a [Incident],
+    messages: &[String],
+    shown_incidents: &mut HashSet<String>,
+) -> Option<&'a Incident> {
+    shown_incidents.retain(|key| incidents.iter().any(|i| &i.get_key() == key));
+    let mut seen_descriptions: HashSet<&str> = messages.iter().map(|m| m.trim()).collect();
+    let next_incident = incidents.iter().find(|incident| {
+        seen_descriptions.insert(incident.description.trim())
+            && !shown_incidents.contains(&incident.get_key())
+    })?;
+    shown_incidents.insert(next_incident.get_key());
+    Some(next_incident)
+}
+
+// Sleeps until the next scheduled alert, or until the alert messages are edited
+async fn wait_for_next_alert(
+    widgets_rx: &mut Receiver<Option<WidgetSnapshot>>,
+    key: &str,
+    shown_messages: &[String],
+    duration: Duration,
+) {
+    let idle = tokio::time::sleep(duration);
+    tokio::pin!(idle);
+    loop {
+        tokio::select! {
+            _ = &mut idle => return,
+            changed = widgets_rx.changed() => {
+                if changed.is_err() {
+                    return idle.await;
+                }
+                let messages = widgets_rx
+                    .borrow_and_update()
+                    .as_ref()
+                    .and_then(|snapshot| AlertWidget::from_snapshot(snapshot, key))
+                    .map(|widget| widget.get_messages());
+                if messages.is_some_and(|messages| messages != shown_messages) {
+                    info!(target: "alert_state_update", "Alerts changed, showing now.");
+                    return;
+                }
+            }
+        }
+    }
+}
+
 pub fn render_alert_display<D>(state: AlertState, canvas: &mut D)
 where
     D: DrawTarget<Color = Rgb888>,
     <D as DrawTarget>::Error: Debug,
 {
+    let (screen_width, screen_height) = get_screen_size(canvas);
     let border_rect_style = PrimitiveStyle::with_fill(Rgb888::YELLOW);
     let invisible_style = PrimitiveStyle::with_fill(Rgb888::BLACK);
     const RECT_WIDTH: i32 = 5;
 
-    for i in 0..(SCREEN_WIDTH as i32 / RECT_WIDTH / 2 + 1) {
+    for i in 0..(screen_width / RECT_WIDTH / 2 + 1) {
         // Top row
         Rectangle::with_corners(
             Point::new(i * RECT_WIDTH * 2, 0),
@@ -161,8 +308,8 @@ where
 
         // Bottom row
         Rectangle::with_corners(
-            Point::new(i * RECT_WIDTH * 2, SCREEN_HEIGHT as i32 - RECT_WIDTH),
-            Point::new(RECT_WIDTH + i * RECT_WIDTH * 2 - 1, SCREEN_HEIGHT as i32),
+            Point::new(i * RECT_WIDTH * 2, screen_height - RECT_WIDTH),
+            Point::new(RECT_WIDTH + i * RECT_WIDTH * 2 - 1, screen_height),
         )
         .into_styled(
             if state.mode == AlertMode::IntroA || state.mode == AlertMode::MessageA {
@@ -177,11 +324,11 @@ where
         Rectangle::with_corners(
             Point::new(
                 (i * RECT_WIDTH * 2) + RECT_WIDTH,
-                SCREEN_HEIGHT as i32 - RECT_WIDTH,
+                screen_height - RECT_WIDTH,
             ),
             Point::new(
                 (RECT_WIDTH + i * RECT_WIDTH * 2 - 1) + RECT_WIDTH,
-                SCREEN_HEIGHT as i32,
+                screen_height,
             ),
         )
         .into_styled(
@@ -215,7 +362,7 @@ where
         .build();
     let bounds = Rectangle::with_corners(
         Point::new(0, RECT_WIDTH),
-        Point::new(SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32 - RECT_WIDTH),
+        Point::new(screen_width, screen_height - RECT_WIDTH),
     );
 
     if state.mode == AlertMode::IntroA || state.mode == AlertMode::IntroB {
@@ -255,3 +402,138 @@ where
         }
     }
 }
+
+// Hand-entered alerts and WMATA incidents, taking over the whole screen
+pub struct AlertDisplay;
+
+impl Widget for AlertDisplay {
+    type Config = AlertWidget;
+    type State = AlertState;
+    const PRIORITY: i32 = PRIORITY_ALERT;
+
+    fn initial_state() -> AlertState {
+        AlertState::blank()
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<AlertState>,
+    ) -> JoinHandle<()> {
+        spawn_alert_update_task(WmataClient::from_env(), key, widgets_rx, state_tx)
+    }
+
+    fn get_visibility(state: &AlertState, _now: DateTime<Utc>) -> Visibility {
+        match state.mode {
+            AlertMode::Hidden => Visibility::Hidden,
+            _ => Visibility::Visible,
+        }
+    }
+
+    fn render(state: &AlertState, _now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        render_alert_display(state.clone(), canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::firebase::fixture_config;
+
+    use super::*;
+
+    fn incident(id: &str, description: &str, lines: &str) -> Incident {
+        Incident {
+            incident_id: id.to_string(),
+            description: description.to_string(),
+            lines_affected: lines.to_string(),
+            date_updated: String::from("2025-01-06T08:00:00"),
+        }
+    }
+
+    fn alert_widget(incident_lines: serde_json::Value) -> AlertWidget {
+        fixture_config(json!({
+            "show_incidents": true,
+            "incident_lines": incident_lines,
+        }))
+    }
+
+    fn next_description(
+        incidents: &[Incident],
+        messages: &[&str],
+        shown_incidents: &mut HashSet<String>,
+    ) -> Option<String> {
+        let messages: Vec<String> = messages.iter().map(|m| m.to_string()).collect();
+        choose_next_incident(incidents, &messages, shown_incidents)
+            .map(|incident| incident.description.clone())
+    }
+
+    #[test]
+    fn lines_affected_are_split_on_semicolons() {
+        assert_eq!(incident("1", "", "RD; OR;").get_lines(), ["RD", "OR"]);
+        assert!(incident("1", "", " ;").get_lines().is_empty());
+    }
+
+    #[test]
+    fn incidents_are_filtered_by_line() {
+        let red_and_orange = incident("1", "", "RD; OR;");
+        let silver = incident("2", "", "SV;");
+        let widget_for_orange = alert_widget(json!(["or"]));
+        assert!(widget_for_orange.wants_incident_lines(&red_and_orange.get_lines()));
+        assert!(!widget_for_orange.wants_incident_lines(&silver.get_lines()));
+        let widget_for_all = alert_widget(json!(null));
+        assert!(widget_for_all.wants_incident_lines(&silver.get_lines()));
+    }
+
+    #[test]
+    fn each_incident_is_shown_once() {
+        let incidents = [
+            incident("1", "Delays", "RD;"),
+            incident("2", "Single tracking", "OR;"),
+        ];
+        let mut shown = HashSet::new();
+        assert_eq!(
+            next_description(&incidents, &[], &mut shown).as_deref(),
+            Some("Delays")
+        );
+        assert_eq!(
+            next_description(&incidents, &[], &mut shown).as_deref(),
+            Some("Single tracking")
+        );
+        assert_eq!(next_description(&incidents, &[], &mut shown), None);
+    }
+
+    #[test]
+    fn updated_incidents_are_shown_again() {
+        let mut shown = HashSet::new();
+        let incidents = [incident("1", "Delays", "RD;")];
+        next_description(&incidents, &[], &mut shown);
+        let updated = [Incident {
+            date_updated: String::from("2025-01-06T09:00:00"),
+            ..incident("1", "Delays", "RD;")
+        }];
+        assert_eq!(
+            next_description(&updated, &[], &mut shown).as_deref(),
+            Some("Delays")
+        );
+        // Resolved incidents are forgotten
+        assert_eq!(shown.len(), 1);
+    }
+
+    #[test]
+    fn incidents_repeating_an_alert_are_skipped() {
+        let incidents = [
+            incident("1", "Elevator out at Metro Center ", "RD;"),
+            incident("2", "Delays", "RD;"),
+            incident("3", "Delays", "OR;"),
+        ];
+        let mut shown = HashSet::new();
+        let messages = ["Elevator out at Metro Center"];
+        assert_eq!(
+            next_description(&incidents, &messages, &mut shown).as_deref(),
+            Some("Delays")
+        );
+        assert_eq!(next_description(&incidents, &messages, &mut shown), None);
+    }
+}
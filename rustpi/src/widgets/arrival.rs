@@ -1,34 +1,62 @@
-use std::{cmp::Ordering, env, error::Error, fmt::Debug, time::Duration};
+use std::{
+    cmp::Ordering,
+    env,
+    error::Error,
+    fmt::{Debug, Display},
+    future::Future,
+    path::PathBuf,
+    time::Duration,
+};
 
 use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
 use embedded_graphics::{
-    mono_font::{ascii::FONT_7X14, MonoTextStyle},
+    draw_target::DrawTargetExt,
+    mono_font::MonoTextStyle,
     pixelcolor::Rgb888,
-    prelude::{DrawTarget, Point, Primitive},
+    prelude::{DrawTarget, Point, Primitive, Size},
     primitives::{PrimitiveStyle, Rectangle},
-    text::Text,
+    text::{renderer::TextRenderer, Alignment, Baseline, Text},
     Drawable,
 };
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use serde_json::from_str;
-use tokio::{spawn, sync::watch::Sender, task::JoinHandle};
-
-use crate::firebase::{ArrivalMessage, ArrivalWidget, LoadableWidget};
-use log::{debug, info};
+use serde_json::{from_str, Value};
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
 
-use super::{LINE_HEIGHT, LINE_HEIGHT_WITH_PADDING, MAX_LINES, SCREEN_WIDTH};
+use crate::{
+    firebase::{
+        get_latest_widget, get_widget_config, wait_for_widget_edit, ArrivalMessage, ArrivalPaging,
+        ArrivalWidget, TextOverflow, WidgetSnapshot,
+    },
+    fonts::{BdfFont, BdfTextStyle},
+    led::ScreenCanvas,
+    wmata::WmataClient,
+};
+use log::{debug, error, info, warn};
+
+use super::{
+    clock::{ClockFormat, StatusBar},
+    get_screen_size,
+    layout::BoardLayout,
+    registry::{Visibility, Widget, PRIORITY_BOARD},
+    trip::{plan_trips, Fares, TripInfo, TripPlan},
+};
 
 // These structs are a mess to account for what likely is .NET naming convention.
 
 #[derive(Serialize, Deserialize, Debug)]
-struct PredictionApiReturn {
+pub struct PredictionApiReturn {
     #[serde(rename(deserialize = "Trains"))]
     trains: Vec<Train>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct Train {
+pub struct Train {
     #[serde(rename(deserialize = "Car"))]
     car: String,
     #[serde(rename(deserialize = "Destination"))]
@@ -51,6 +79,12 @@ impl Train {
     fn get_line_enum(self: &Self) -> Line {
         get_string_line(&self.line)
     }
+
+    fn is_no_passenger(&self) -> bool {
+        self.destination == "No Passenger"
+            || self.destination == "NoPssenger"
+            || self.destination == "ssenger"
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -60,6 +94,19 @@ pub struct TrainDisplayEntry {
     destination: String,
     arrival: String, // Can be in minutes or ARR, BRD
     arrival_timestamp: DateTime<Utc>,
+    walk_time: TimeDelta,
+    trip: Option<TripInfo>, // To the destination station, in trip mode
+    timezone: Tz,           // Of the trip arrival time
+}
+
+impl TrainDisplayEntry {
+    // A train is catchable if we can still walk to the platform before it arrives
+    fn is_catchable(&self) -> bool {
+        match self.arrival.parse::<i64>() {
+            Ok(_) => self.arrival_timestamp - self.walk_time >= Utc::now(),
+            Err(_) => self.arrival != "ARR" && self.arrival != "BRD",
+        }
+    }
 }
 
 pub trait ArrivalDisplayable {
@@ -68,10 +115,22 @@ pub trait ArrivalDisplayable {
     fn get_message(&self) -> String;
     fn get_line(&self) -> Line;
     fn get_line_color(&self) -> Rgb888;
+    // Shown in the LN column, e.g. a bus route
+    fn get_line_label(&self) -> String {
+        get_line_string(self.get_line())
+    }
     fn pretty_print(&self) -> String;
     fn get_leave(&self) -> String;
     fn is_sticky(&self) -> bool;
     fn get_arrival_time(&self) -> String;
+    // In trip mode, the trip to the destination station
+    fn get_trip(&self) -> Option<TripInfo> {
+        None
+    }
+    // In trip mode, the fare for the trip on this train
+    fn get_fare(&self) -> Option<f64> {
+        None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -81,9 +140,11 @@ pub struct SimpleArrivalDisplayable {
     message: String,
     line: Line,
     line_color: Rgb888,
+    line_label: String,
     leave: String,
     is_sticky: bool,
     arrival_time: String,
+    fare: Option<f64>,
 }
 
 impl SimpleArrivalDisplayable {
@@ -94,9 +155,66 @@ impl SimpleArrivalDisplayable {
             message: String::from("Loading..."),
             line: Line::UNKNOWN,
             line_color: Rgb888::new(50, 50, 50),
+            line_label: get_line_string(Line::UNKNOWN),
             leave: String::from(""),
             is_sticky: true,
             arrival_time: String::from(""),
+            fare: None,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        SimpleArrivalDisplayable {
+            message: String::from("Disabled"),
+            ..SimpleArrivalDisplayable::loading()
+        }
+    }
+
+    // Snapshot of a row, so the state sent to the main thread is plain data
+    pub fn from_displayable(displayable: &dyn ArrivalDisplayable) -> Self {
+        SimpleArrivalDisplayable {
+            comparison_timestamp: displayable.get_comparison_timestamp(),
+            comparison_timestamp_no_sticky: displayable.get_comparison_timestamp_no_sticky(),
+            message: displayable.get_message(),
+            line: displayable.get_line(),
+            line_color: displayable.get_line_color(),
+            line_label: displayable.get_line_label(),
+            is_sticky: displayable.is_sticky(),
+            leave: displayable.get_leave(),
+            arrival_time: displayable.get_arrival_time(),
+            fare: displayable.get_fare(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl SimpleArrivalDisplayable {
+    pub fn fixture(line: Line, message: &str, leave: &str, arrival_time: &str) -> Self {
+        SimpleArrivalDisplayable {
+            comparison_timestamp: DateTime::from_timestamp(0, 0).unwrap(),
+            comparison_timestamp_no_sticky: DateTime::from_timestamp(0, 0).unwrap(),
+            message: message.to_string(),
+            line,
+            line_color: get_line_color(line),
+            line_label: get_line_string(line),
+            leave: leave.to_string(),
+            is_sticky: false,
+            arrival_time: arrival_time.to_string(),
+            fare: None,
+        }
+    }
+
+    pub fn with_fare(self, fare: f64) -> Self {
+        SimpleArrivalDisplayable {
+            fare: Some(fare),
+            ..self
+        }
+    }
+
+    pub fn with_line_label(self, line_label: &str) -> Self {
+        SimpleArrivalDisplayable {
+            line_label: line_label.to_string(),
+            ..self
         }
     }
 }
@@ -122,6 +240,10 @@ impl ArrivalDisplayable for SimpleArrivalDisplayable {
         self.line_color
     }
 
+    fn get_line_label(&self) -> String {
+        self.line_label.clone()
+    }
+
     fn get_leave(&self) -> String {
         self.leave.clone()
     }
@@ -135,13 +257,23 @@ impl ArrivalDisplayable for SimpleArrivalDisplayable {
     }
 
     fn pretty_print(&self) -> String {
-        format!("{} {}", get_line_string(self.line), self.message)
+        format!("{} {}", self.line_label, self.message)
+    }
+
+    fn get_fare(&self) -> Option<f64> {
+        self.fare
     }
 }
 
 impl ArrivalDisplayable for TrainDisplayEntry {
     fn get_message(&self) -> String {
-        self.destination.clone()
+        match self.trip {
+            Some(trip) => (self.arrival_timestamp + TimeDelta::minutes(trip.rail_minutes))
+                .with_timezone(&self.timezone)
+                .format("%H:%M")
+                .to_string(),
+            None => self.destination.clone(),
+        }
     }
 
     fn get_line(&self) -> Line {
@@ -185,15 +317,7 @@ impl ArrivalDisplayable for TrainDisplayEntry {
 
     fn get_leave(&self) -> String {
         match self.arrival.parse::<i64>() {
-            Ok(_) => {
-                if self.arrival_timestamp - TimeDelta::minutes(15) > Utc::now() {
-                    (self.arrival_timestamp - TimeDelta::minutes(15) - Utc::now())
-                        .num_minutes()
-                        .to_string()
-                } else {
-                    "- ".to_string()
-                }
-            }
+            Ok(_) => get_leave_string(self.arrival_timestamp, self.walk_time),
             Err(_) => "- ".to_string(),
         }
     }
@@ -201,6 +325,18 @@ impl ArrivalDisplayable for TrainDisplayEntry {
     fn get_arrival_time(&self) -> String {
         self.arrival.clone()
     }
+
+    fn get_trip(&self) -> Option<TripInfo> {
+        self.trip
+    }
+
+    // Peak or not depends on when the train comes
+    fn get_fare(&self) -> Option<f64> {
+        self.trip.map(|trip| {
+            trip.get_fares(self.arrival_timestamp, self.timezone)
+                .current
+        })
+    }
 }
 
 impl ArrivalDisplayable for ArrivalMessage {
@@ -243,13 +379,7 @@ impl ArrivalDisplayable for ArrivalMessage {
     }
 
     fn get_leave(&self) -> String {
-        if self.get_comparison_timestamp() - TimeDelta::minutes(15) > Utc::now() {
-            (Utc::now() - self.get_comparison_timestamp() - TimeDelta::minutes(15))
-                .num_minutes()
-                .to_string()
-        } else {
-            "- ".to_string()
-        }
+        get_leave_string(self.get_comparison_timestamp_no_sticky(), self.walk_time)
     }
 
     fn get_arrival_time(&self) -> String {
@@ -337,9 +467,141 @@ impl Eq for Box<dyn ArrivalDisplayable> {}
 pub struct ArrivalState {
     pub messages: Vec<SimpleArrivalDisplayable>,
     pub last_update: DateTime<Utc>,
+    pub paging: Option<ArrivalPaging>,
+    pub stale_after: TimeDelta,
+    pub fares: Option<Fares>, // Trip mode only
+    pub status_bar: Option<ClockFormat>,
+    pub text_overflow: TextOverflow,
+}
+
+impl ArrivalState {
+    pub fn loading() -> Self {
+        ArrivalState {
+            messages: vec![SimpleArrivalDisplayable::loading()],
+            last_update: Utc::now(),
+            paging: None,
+            stale_after: TimeDelta::MAX,
+            fares: None,
+            status_bar: None,
+            text_overflow: TextOverflow::default(),
+        }
+    }
+
+    fn get_status_bar(&self, now: DateTime<Utc>) -> Option<StatusBar> {
+        self.status_bar.map(|format| StatusBar { format, now })
+    }
+
+    pub fn current_page(
+        &self,
+        rows: usize,
+        now: DateTime<Utc>,
+    ) -> (Vec<SimpleArrivalDisplayable>, ArrivalFrame) {
+        let stale_minutes = get_stale_minutes(self.last_update, self.stale_after, now);
+        match self.paging {
+            Some(paging) if paging.enabled => {
+                let (messages, page) = paginate(&self.messages, &paging, rows, now);
+                (
+                    messages,
+                    ArrivalFrame {
+                        page,
+                        stale_minutes,
+                        fares: self.fares,
+                        status_bar: self.get_status_bar(now),
+                        text_overflow: self.text_overflow,
+                        now,
+                    },
+                )
+            }
+            _ => (
+                self.messages.clone(),
+                ArrivalFrame {
+                    page: None,
+                    stale_minutes,
+                    fares: self.fares,
+                    status_bar: self.get_status_bar(now),
+                    text_overflow: self.text_overflow,
+                    now,
+                },
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageIndicator {
+    pub index: usize,
+    pub count: usize,
+}
+
+// Everything about a frame of the arrival board that isn't one of the rows
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ArrivalFrame {
+    pub page: Option<PageIndicator>,
+    pub stale_minutes: Option<i64>, // Set once the last successful update is older than the threshold
+    pub fares: Option<Fares>,       // Replace LN DEST in the header in trip mode
+    pub status_bar: Option<StatusBar>,
+    pub text_overflow: TextOverflow,
+    pub now: DateTime<Utc>, // How far long text has scrolled
+}
+
+// Minutes since the last successful update, once that's longer than the threshold
+pub(super) fn get_stale_minutes(
+    last_update: DateTime<Utc>,
+    stale_after: TimeDelta,
+    now: DateTime<Utc>,
+) -> Option<i64> {
+    let age = now - last_update;
+    (age > stale_after).then(|| age.num_minutes())
+}
+
+pub(super) fn get_stale_header(minutes: i64) -> (String, Rgb888) {
+    (
+        format!("DATA {} MIN OLD", minutes),
+        Rgb888::new(255, 160, 0),
+    )
+}
+
+const DEFAULT_PAGE_SECONDS: i64 = 8;
+
+// `rows` is how many fit on the board at once
+pub fn paginate<T>(
+    messages: &[T],
+    paging: &ArrivalPaging,
+    rows: usize,
+    now: DateTime<Utc>,
+) -> (Vec<T>, Option<PageIndicator>)
+where
+    T: ArrivalDisplayable + Clone,
+{
+    let (mut pinned, rest): (Vec<T>, Vec<T>) = if paging.pin_sticky.unwrap_or(false) {
+        messages.iter().cloned().partition(|m| m.is_sticky())
+    } else {
+        (Vec::new(), messages.to_vec())
+    };
+    // Leave at least one row for the trains, the sticky messages that don't fit page with them
+    let unpinned = pinned.split_off(pinned.len().min(rows.saturating_sub(1)));
+    let rest: Vec<T> = unpinned.into_iter().chain(rest).collect();
+    let per_page = rows - pinned.len();
+
+    if per_page == 0 || rest.len() <= per_page {
+        return (pinned.into_iter().chain(rest).take(rows).collect(), None);
+    }
+
+    let count = rest.len().div_ceil(per_page);
+    let interval = paging
+        .interval_seconds
+        .unwrap_or(DEFAULT_PAGE_SECONDS)
+        .max(1);
+    let index = (now.timestamp() / interval) as usize % count;
+    let rows = pinned
+        .into_iter()
+        .chain(rest.into_iter().skip(index * per_page).take(per_page))
+        .collect();
+    (rows, Some(PageIndicator { index, count }))
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)] // Named like WMATA's line codes
 pub enum Line {
     RD,
     OR,
@@ -348,13 +610,13 @@ pub enum Line {
     GR,
     BL,
     TS,
+    BUS,
     UNKNOWN,
 }
 
-const API_URL: &str = "https://api.wmata.com/StationPrediction.svc/json/GetPrediction/";
-const API_KEY_HEADER: &str = "api_key";
+const PREDICTION_PATH: &str = "/StationPrediction.svc/json/GetPrediction/";
 
-fn get_line_color(line: Line) -> Rgb888 {
+pub(super) fn get_line_color(line: Line) -> Rgb888 {
     match line {
         Line::RD => Rgb888::new(255, 0, 0),
         Line::OR => Rgb888::new(255, 85, 0),
@@ -363,11 +625,12 @@ fn get_line_color(line: Line) -> Rgb888 {
         Line::BL => Rgb888::new(0, 0, 255),
         Line::TS => Rgb888::new(0, 51, 160),
         Line::SV => Rgb888::new(170, 170, 170),
+        Line::BUS => Rgb888::new(200, 16, 46),
         _ => Rgb888::new(110, 110, 110),
     }
 }
 
-fn get_line_string(line: Line) -> String {
+pub(super) fn get_line_string(line: Line) -> String {
     match line {
         Line::RD => String::from("RD"),
         Line::OR => String::from("OR"),
@@ -376,11 +639,21 @@ fn get_line_string(line: Line) -> String {
         Line::BL => String::from("BL"),
         Line::TS => String::from("TS"),
         Line::SV => String::from("SV"),
+        Line::BUS => String::from("BU"),
         Line::UNKNOWN => String::from("??"),
     }
 }
 
-fn get_string_line(line: &str) -> Line {
+// Minutes until you need to leave to make an arrival, given the walk time to the platform
+pub(super) fn get_leave_string(arrival: DateTime<Utc>, walk_time: TimeDelta) -> String {
+    if arrival - walk_time > Utc::now() {
+        (arrival - walk_time - Utc::now()).num_minutes().to_string()
+    } else {
+        "- ".to_string()
+    }
+}
+
+pub(super) fn get_string_line(line: &str) -> Line {
     match line {
         "RD" => Line::RD,
         "OR" => Line::OR,
@@ -393,160 +666,352 @@ fn get_string_line(line: &str) -> Line {
     }
 }
 
-pub async fn get_latest_state(
-    arrival_state: ArrivalWidget,
-) -> Result<Vec<SimpleArrivalDisplayable>, Box<dyn Error>> {
-    let mut url = API_URL.to_owned();
-    url.push_str(&arrival_state.station_id);
-
-    let client = reqwest::Client::new();
-
-    match client
-        .get(url)
-        .header(API_KEY_HEADER, env::var("WMATA_API_KEY").unwrap())
-        .send()
-        .await
-    {
-        Ok(resp) => {
-            let raw = resp.bytes().await.expect("API did not respond");
-            let raw_string = String::from_utf8(raw.to_vec()).expect("Response has invalid UTF-8");
-            debug!(target: "arrival_state_update", "{}", raw_string);
-            let api_return = from_str(&raw_string).expect("Could not deserialize to JSON");
-            let converted = convert_api_return_to_display(api_return, arrival_state.messages);
-            let result: Vec<SimpleArrivalDisplayable> = converted
-                .iter()
-                .map(|f| SimpleArrivalDisplayable {
-                    comparison_timestamp: f.get_comparison_timestamp(),
-                    comparison_timestamp_no_sticky: f.get_comparison_timestamp_no_sticky(),
-                    message: f.get_message(),
-                    line: f.get_line(),
-                    line_color: f.get_line_color(),
-                    is_sticky: f.is_sticky(),
-                    leave: f.get_leave(),
-                    arrival_time: f.get_arrival_time(),
-                })
-                .collect();
-            Ok(result)
-        }
-        Err(err) => {
-            println!("Reqwest Error: {}", err);
-            Err(Box::new(err))
-        }
+pub trait PredictionSource {
+    fn get_predictions(
+        &self,
+        station_codes: &[String],
+    ) -> impl Future<Output = Result<PredictionApiReturn, Box<dyn Error + Send + Sync>>> + Send;
+}
+
+pub struct WmataPredictionSource {
+    client: WmataClient,
+}
+
+impl WmataPredictionSource {
+    pub fn new(client: WmataClient) -> Self {
+        WmataPredictionSource { client }
+    }
+}
+
+impl PredictionSource for WmataPredictionSource {
+    async fn get_predictions(
+        &self,
+        station_codes: &[String],
+    ) -> Result<PredictionApiReturn, Box<dyn Error + Send + Sync>> {
+        // WMATA accepts several station codes joined by commas and returns all of their trains together
+        let path = format!("{}{}", PREDICTION_PATH, station_codes.join(","));
+        self.client.get(&path, &[]).await
     }
 }
 
+// Serves a saved GetPrediction response, for running offline or against a fixture
+pub struct FilePredictionSource {
+    path: PathBuf,
+}
+
+impl FilePredictionSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FilePredictionSource { path: path.into() }
+    }
+}
+
+impl PredictionSource for FilePredictionSource {
+    async fn get_predictions(
+        &self,
+        _station_codes: &[String],
+    ) -> Result<PredictionApiReturn, Box<dyn Error + Send + Sync>> {
+        let raw_string = tokio::fs::read_to_string(&self.path).await?;
+        Ok(from_str(&raw_string)?)
+    }
+}
+
+pub async fn get_latest_state<S: PredictionSource>(
+    source: &S,
+    client: &WmataClient,
+    arrival_state: ArrivalWidget,
+) -> Result<ArrivalState, Box<dyn Error + Send + Sync>> {
+    let api_return = source
+        .get_predictions(&arrival_state.get_station_codes())
+        .await?;
+    // Trip mode: work out which trains stop at the destination, and when they get there
+    let destination_codes = arrival_state.get_destination_codes();
+    let trips = match destination_codes.is_empty() {
+        true => None,
+        false => Some(
+            plan_trips(
+                client,
+                api_return
+                    .trains
+                    .iter()
+                    .filter_map(|train| {
+                        Some((train.location_code.clone(), train.destination_code.clone()?))
+                    })
+                    .collect(),
+                &destination_codes,
+            )
+            .await,
+        ),
+    };
+    let converted = convert_api_return_to_display(api_return, &arrival_state, trips.as_ref());
+    let result: Vec<SimpleArrivalDisplayable> = converted
+        .iter()
+        .map(|f| SimpleArrivalDisplayable::from_displayable(f.as_ref()))
+        .collect();
+    Ok(ArrivalState {
+        messages: result,
+        last_update: Utc::now(),
+        paging: arrival_state.paging,
+        stale_after: TimeDelta::minutes(arrival_state.get_stale_minutes()),
+        status_bar: arrival_state.status_bar.as_ref().map(ClockFormat::from),
+        text_overflow: arrival_state.text_overflow.unwrap_or_default(),
+        // The fare of the first train on the board
+        fares: converted
+            .iter()
+            .find_map(|entry| entry.get_trip())
+            .map(|trip| trip.get_fares(Utc::now(), arrival_state.get_timezone())),
+    })
+}
+
+fn get_trip<'a>(trips: &'a TripPlan, train: &Train) -> Option<&'a TripInfo> {
+    trips
+        .get(&(train.location_code.clone(), train.destination_code.clone()?))?
+        .as_ref()
+}
+
 fn convert_api_return_to_display(
     response: PredictionApiReturn,
-    extra_messages: Option<Vec<ArrivalMessage>>,
+    widget: &ArrivalWidget,
+    trips: Option<&TripPlan>,
 ) -> Vec<Box<dyn ArrivalDisplayable>> {
-    let extra_msg: Vec<Box<dyn ArrivalDisplayable>> = extra_messages
+    let extra_msg: Vec<Box<dyn ArrivalDisplayable>> = widget
+        .messages
+        .clone()
         .unwrap_or(Vec::new())
         .iter()
         .filter(|v| (v.get_comparison_timestamp_no_sticky() - Utc::now()).num_seconds() >= 0) // Filter out custom messages that have expired
-        .map(|v| Box::new(v.clone()) as _)
+        .map(|v| {
+            Box::new(ArrivalMessage {
+                walk_time: TimeDelta::minutes(
+                    widget.get_walk_minutes(&get_line_string(v.get_line())),
+                ),
+                ..v.clone()
+            }) as _
+        })
         .collect();
     response
         .trains
         .iter()
+        .unique_by(|train| {
+            (
+                &train.line,
+                &train.destination,
+                &train.group,
+                &train.location_code,
+                &train.min,
+                &train.car,
+            )
+        })
+        // In trip mode, only trains that stop at the destination
+        .filter(|train| trips.is_none_or(|trips| get_trip(trips, train).is_some()))
+        .filter(|train| {
+            widget.filters.as_ref().is_none_or(|filters| {
+                filters.allows(
+                    &train.line,
+                    &train.group,
+                    train.destination_code.as_deref(),
+                    train.is_no_passenger(),
+                )
+            })
+        })
         .map(|train| {
             let arrival_as_number = train.min.parse::<i64>();
+            let arrival_timestamp = Utc::now()
+                + TimeDelta::minutes(if arrival_as_number.is_ok() {
+                    arrival_as_number.unwrap()
+                } else {
+                    0
+                });
 
-            Box::new(TrainDisplayEntry {
+            TrainDisplayEntry {
                 arrival: train.min.clone(),
-                arrival_timestamp: Utc::now()
-                    + TimeDelta::minutes(if arrival_as_number.is_ok() {
-                        arrival_as_number.unwrap()
-                    } else {
-                        0
-                    }),
-                destination: if train.destination == "No Passenger"
-                    || train.destination == "NoPssenger"
-                    || train.destination == "ssenger"
-                {
-                    "No Psngr".to_string()
+                arrival_timestamp,
+                destination: if train.is_no_passenger() {
+                    "No Passenger".to_string()
                 } else {
                     train.destination.clone()
                 },
                 line: train.get_line_enum(),
                 line_color: get_line_color(train.get_line_enum()),
-            }) as _
+                walk_time: TimeDelta::minutes(widget.get_walk_minutes(&train.line)),
+                trip: trips.and_then(|trips| get_trip(trips, train)).copied(),
+                timezone: widget.get_timezone(),
+            }
         })
+        // Trip mode only lists trains you can still make
+        .filter(|entry| {
+            !(widget.should_hide_uncatchable() || trips.is_some()) || entry.is_catchable()
+        })
+        .map(|entry| Box::new(entry) as _)
         .chain(extra_msg)
         .sorted()
         .collect()
 }
 
-pub fn render_arrival_display<D, T>(state: Vec<T>, canvas: &mut D)
+pub fn render_arrival_display<D, T>(state: Vec<T>, frame: ArrivalFrame, canvas: &mut D)
 where
     D: DrawTarget<Color = Rgb888>,
     <D as DrawTarget>::Error: Debug,
     T: ArrivalDisplayable,
 {
-    let white_text_style = MonoTextStyle::new(&FONT_7X14, Rgb888::new(255, 255, 255));
-    // Header
-    let header_text_style = MonoTextStyle::new(&FONT_7X14, Rgb888::new(120, 120, 120));
+    let (screen_width, _) = get_screen_size(canvas);
+    let layout = BoardLayout::with_status_bar(canvas, frame.status_bar.is_some());
+    let white_text_style = BdfTextStyle::new(layout.font, Rgb888::new(255, 255, 255));
+
+    // Status bar, in its own row above the header: the time on the left and the date on the right
+    if let (Some(status_bar), Some(baseline)) = (frame.status_bar, layout.status_baseline) {
+        let status_style = MonoTextStyle::new(layout.small_font, Rgb888::new(120, 120, 120));
+        Text::new(
+            &status_bar.get_time(),
+            Point::new(layout.get_header_x(0), baseline),
+            status_style,
+        )
+        .draw(canvas)
+        .unwrap();
+        Text::with_alignment(
+            &status_bar.get_date(),
+            Point::new(screen_width - 1, baseline),
+            status_style,
+            Alignment::Right,
+        )
+        .draw(canvas)
+        .unwrap();
+    }
+
+    // Header, replaced by a warning when the data is old. Trip mode's fares (the one now, then
+    // the other one of the day) replace LN DEST.
+    let header_right = match layout.leave_x {
+        Some(_) => "LV MIN",
+        None => "MIN",
+    };
+    let right_column = layout.header_columns.saturating_sub(header_right.len());
+    let header_left = get_header_left(frame.fares, right_column);
+    let (header, header_color) = match frame.stale_minutes {
+        Some(minutes) => get_stale_header(minutes),
+        None => (
+            format!(
+                "{:<width$}{}",
+                header_left,
+                header_right,
+                width = right_column
+            ),
+            Rgb888::new(120, 120, 120),
+        ),
+    };
     Text::new(
-        "LN DEST     LV MIN",
-        Point::new(1, LINE_HEIGHT),
-        header_text_style,
+        &header,
+        Point::new(layout.get_header_x(0), layout.header_baseline),
+        BdfTextStyle::new(layout.font, header_color),
     )
     .draw(canvas)
     .unwrap();
 
+    // Page indicator, centered in the gap between DEST and LV if there's room
+    if let (Some(page), None) = (frame.page, frame.stale_minutes) {
+        let indicator = format!("{}/{}", page.index + 1, page.count);
+        let indicator_width =
+            indicator.len() as i32 * layout.small_font.character_size.width as i32;
+        let gap_start = layout.get_header_x(header_left.len());
+        let gap_end = layout.get_header_x(right_column);
+        if indicator_width < gap_end - gap_start {
+            Text::new(
+                &indicator,
+                Point::new(
+                    gap_start + (gap_end - gap_start - indicator_width) / 2,
+                    layout.header_baseline,
+                ),
+                MonoTextStyle::new(layout.small_font, Rgb888::new(90, 90, 90)),
+            )
+            .draw(canvas)
+            .unwrap();
+        }
+    }
+
     // Line below header
     Rectangle::with_corners(
-        Point::new(0, LINE_HEIGHT_WITH_PADDING),
-        Point::new(SCREEN_WIDTH as i32, LINE_HEIGHT_WITH_PADDING),
+        Point::new(0, layout.divider_y),
+        Point::new(screen_width, layout.divider_y),
     )
     .into_styled(PrimitiveStyle::with_fill(Rgb888::new(40, 40, 40)))
     .draw(canvas)
     .unwrap();
 
-    for (index, message) in state.iter().enumerate().take(MAX_LINES) {
-        // Draw left rectangle
-        Rectangle::with_corners(
-            Point::new(1, LINE_HEIGHT_WITH_PADDING * (index as i32 + 2)),
-            Point::new(
-                2,
-                LINE_HEIGHT_WITH_PADDING * (index as i32 + 1)
-                    + (LINE_HEIGHT_WITH_PADDING - LINE_HEIGHT),
-            ),
-        )
-        .into_styled(PrimitiveStyle::with_fill(message.get_line_color()))
-        .draw(canvas)
-        .unwrap();
+    for (index, message) in state.iter().enumerate().take(layout.rows) {
+        let baseline = layout.get_row_baseline(index);
 
-        // Draw line text
-        Text::new(
-            &get_line_string(message.get_line()),
-            Point::new(4, LINE_HEIGHT_WITH_PADDING * (index as i32 + 2)),
-            MonoTextStyle::new(&FONT_7X14, message.get_line_color()),
-        )
-        .draw(canvas)
-        .unwrap();
+        // Draw left rectangle
+        layout
+            .get_marker(index)
+            .into_styled(PrimitiveStyle::with_fill(message.get_line_color()))
+            .draw(canvas)
+            .unwrap();
+
+        // Draw line text, in a narrower font if it wouldn't fit the column (e.g. bus routes)
+        let line_label = message.get_line_label();
+        let line_position = Point::new(layout.line_x, baseline);
+        if line_label.len() > 2 {
+            Text::new(
+                &line_label,
+                line_position,
+                MonoTextStyle::new(layout.label_font, message.get_line_color()),
+            )
+            .draw(canvas)
+            .unwrap();
+        } else {
+            Text::new(
+                &line_label,
+                line_position,
+                BdfTextStyle::new(layout.font, message.get_line_color()),
+            )
+            .draw(canvas)
+            .unwrap();
+        }
 
-        // Draw message
-        Text::new(
+        // Draw message. Rows without times, e.g. "Loading...", can use the whole width.
+        let message_width = match message.get_leave().trim().is_empty()
+            && message.get_arrival_time().trim().is_empty()
+        {
+            true => screen_width - layout.destination_x,
+            false => layout.destination_width,
+        };
+        // In trip mode the train's fare goes at the end of the column, after its arrival time
+        let fare = message.get_fare().map(|fare| format!("${:.2}", fare));
+        let fare_width = fare.as_ref().map_or(0, |fare| {
+            fare.len() as i32 * layout.label_font.character_size.width as i32 + FARE_GAP
+        });
+        draw_fitted_text(
+            canvas,
             &message.get_message(),
-            Point::new(22, LINE_HEIGHT_WITH_PADDING * (index as i32 + 2)),
-            MonoTextStyle::new(&FONT_7X14, message.get_line_color()),
-        )
-        .draw(canvas)
-        .unwrap();
+            Point::new(layout.destination_x, baseline),
+            message_width - fare_width,
+            BdfTextStyle::new(layout.font, message.get_line_color()),
+            frame.text_overflow,
+            frame.now,
+        );
+        if let Some(fare) = fare {
+            Text::new(
+                &fare,
+                Point::new(layout.destination_x + message_width - fare_width, baseline),
+                MonoTextStyle::new(layout.label_font, Rgb888::new(120, 120, 120)),
+            )
+            .draw(canvas)
+            .unwrap();
+        }
 
-        // Draw LEAVE - Custom for this sign to indicate when to leave the office to catch this train (15 minutes before)
-        Text::new(
-            &message.get_leave(),
-            Point::new(85, LINE_HEIGHT_WITH_PADDING * (index as i32 + 2)),
-            white_text_style,
-        )
-        .draw(canvas)
-        .unwrap();
+        // Draw LEAVE - Custom for this sign to indicate when to leave the office to catch this train (walk time before)
+        if let Some(leave_x) = layout.leave_x {
+            Text::new(
+                &message.get_leave(),
+                Point::new(leave_x, baseline),
+                white_text_style,
+            )
+            .draw(canvas)
+            .unwrap();
+        }
 
         // Draw minutes
         Text::new(
             &message.get_arrival_time(),
-            Point::new(106, LINE_HEIGHT_WITH_PADDING * (index as i32 + 2)),
+            Point::new(layout.minutes_x, baseline),
             white_text_style,
         )
         .draw(canvas)
@@ -554,18 +1019,390 @@ where
     }
 }
 
-pub fn spawn_arrival_update_task(state_tx: Sender<ArrivalState>) -> JoinHandle<()> {
+// Between a row's fare and the LV column
+const FARE_GAP: i32 = 2;
+
+// What goes before LV MIN in the header: trip mode's fares, or LN DEST. Both fares if they fit,
+// otherwise just the one now. It always leaves a space before `right_column`.
+fn get_header_left(fares: Option<Fares>, right_column: usize) -> String {
+    let width = right_column.saturating_sub(1);
+    let header_left = match fares {
+        Some(fares) => [
+            format!("${:.2} ({:.2})", fares.current, fares.other),
+            format!("${:.2}", fares.current),
+        ]
+        .into_iter()
+        .find_or_last(|header_left| header_left.len() <= width)
+        .unwrap(),
+        None => String::from("LN DEST"),
+    };
+    header_left.chars().take(width).collect()
+}
+
+// Long text scrolls by this fast, after a pause at the start of every pass so it can be read
+const MARQUEE_PIXELS_PER_SECOND: i64 = 16;
+const MARQUEE_PAUSE_MILLIS: i64 = 2000;
+const MARQUEE_GAP_CHARACTERS: i32 = 3; // Between the end of the text and its start coming around again
+
+// Draws text into a column `width` pixels wide, scrolling or cutting it short if it doesn't fit.
+// `now` is how far it has scrolled.
+pub(super) fn draw_fitted_text<D>(
+    canvas: &mut D,
+    text: &str,
+    position: Point,
+    width: i32,
+    style: BdfTextStyle<Rgb888>,
+    overflow: TextOverflow,
+    now: DateTime<Utc>,
+) where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let font = style.get_font();
+    let text_width = font.get_text_width(text) as i32;
+    if text_width <= width {
+        Text::new(text, position, style).draw(canvas).unwrap();
+        return;
+    }
+    match overflow {
+        TextOverflow::Ellipsis => {
+            Text::new(&truncate_with_ellipsis(font, text, width), position, style)
+                .draw(canvas)
+                .unwrap();
+        }
+        TextOverflow::Marquee => {
+            let period = text_width + MARQUEE_GAP_CHARACTERS * font.get_column_width() as i32;
+            let offset = get_marquee_offset(period, now);
+            let line = style
+                .measure_string(text, position, Baseline::Alphabetic)
+                .bounding_box;
+            let mut column = canvas.clipped(&Rectangle::new(
+                line.top_left,
+                Size::new(width as u32, line.size.height),
+            ));
+            // The start follows the end back in, so one pass ends where the next begins
+            for x in [position.x - offset, position.x - offset + period] {
+                Text::new(text, Point::new(x, position.y), style)
+                    .draw(&mut column)
+                    .unwrap();
+            }
+        }
+    }
+}
+
+// Pixels the text has scrolled left in the current pass
+fn get_marquee_offset(period: i32, now: DateTime<Utc>) -> i32 {
+    let scroll_millis = period as i64 * 1000 / MARQUEE_PIXELS_PER_SECOND;
+    let elapsed = now
+        .timestamp_millis()
+        .rem_euclid(MARQUEE_PAUSE_MILLIS + scroll_millis);
+    ((elapsed - MARQUEE_PAUSE_MILLIS).max(0) * MARQUEE_PIXELS_PER_SECOND / 1000) as i32
+}
+
+fn truncate_with_ellipsis(font: &BdfFont, text: &str, width: i32) -> String {
+    let ellipsis = match font.has_glyph('\u{2026}') {
+        true => "\u{2026}",
+        false => "...",
+    };
+    let available = width - font.get_text_width(ellipsis) as i32;
+    let fitted: String = text
+        .chars()
+        .scan(0, |used, character| {
+            *used += font.get_char_width(character);
+            (*used <= available).then_some(character)
+        })
+        .collect();
+    format!("{}{}", fitted.trim_end(), ellipsis)
+}
+
+pub fn spawn_arrival_update_task<S>(
+    source: S,
+    client: WmataClient,
+    key: String,
+    mut widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<ArrivalState>,
+) -> JoinHandle<()>
+where
+    S: PredictionSource + Send + Sync + 'static,
+{
     spawn(async move {
         loop {
             debug!(target: "arrival_state_update", "Loading new state...");
-            let arrival_displayables = get_latest_state(ArrivalWidget::load().await).await.unwrap();
-            let new_state = ArrivalState {
-                messages: arrival_displayables,
-                last_update: Utc::now(),
+            let snapshot = widgets_rx.borrow_and_update().clone();
+            let config = get_widget_config(&snapshot, &key);
+            let Some(widget) = get_latest_widget::<ArrivalWidget>(snapshot, &key).await else {
+                warn!(target: "arrival_state_update", "Arrival widget is missing, showing it as disabled.");
+                state_tx
+                    .send(ArrivalState {
+                        messages: vec![SimpleArrivalDisplayable::disabled()],
+                        last_update: Utc::now(),
+                        paging: None,
+                        stale_after: TimeDelta::MAX,
+                        fares: None,
+                        status_bar: None,
+                        text_overflow: TextOverflow::default(),
+                    })
+                    .unwrap();
+                wait_for_next_update(&mut widgets_rx, &key, &config).await;
+                continue;
+            };
+            let result = get_latest_state(&source, &client, widget).await;
+            let Some(new_state) =
+                keep_last_state(result, "arrival_state_update", "Could not update arrivals")
+            else {
+                wait_for_next_update(&mut widgets_rx, &key, &config).await;
+                continue;
             };
             info!(target: "arrival_state_update", "New state loaded. Sending to main thread.");
             state_tx.send(new_state).unwrap();
-            tokio::time::sleep(Duration::from_secs(15)).await;
+            wait_for_next_update(&mut widgets_rx, &key, &config).await;
         }
     })
 }
+
+// Logs a failed update and gives back nothing to send, so the board keeps showing the last good
+// state. It marks that state as stale when it gets old.
+pub(super) fn keep_last_state<T, E: Display>(
+    result: Result<T, E>,
+    target: &str,
+    failure: &str,
+) -> Option<T> {
+    result
+        .map_err(|err| error!(target: target, "{}: {}", failure, err))
+        .ok()
+}
+
+// Predictions are polled, but edits to this widget's config are applied as soon as they stream in
+pub(super) async fn wait_for_next_update(
+    widgets_rx: &mut Receiver<Option<WidgetSnapshot>>,
+    key: &str,
+    config: &Option<Value>,
+) {
+    tokio::select! {
+        _ = tokio::time::sleep(Duration::from_secs(15)) => {}
+        _ = wait_for_widget_edit(widgets_rx, key, config) => {
+            debug!(target: "arrival_state_update", "Widget config changed");
+        }
+    }
+}
+
+// The rail arrival board
+pub struct ArrivalDisplay;
+
+impl Widget for ArrivalDisplay {
+    type Config = ArrivalWidget;
+    type State = ArrivalState;
+    const PRIORITY: i32 = PRIORITY_BOARD;
+
+    fn initial_state() -> ArrivalState {
+        ArrivalState::loading()
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<ArrivalState>,
+    ) -> JoinHandle<()> {
+        // PREDICTION_FILE swaps the WMATA API for a saved response, e.g. fixtures/predictions.json
+        match env::var("PREDICTION_FILE") {
+            Ok(path) => spawn_arrival_update_task(
+                FilePredictionSource::new(path),
+                WmataClient::from_env(),
+                key,
+                widgets_rx,
+                state_tx,
+            ),
+            Err(_) => spawn_arrival_update_task(
+                WmataPredictionSource::new(WmataClient::from_env()),
+                WmataClient::from_env(),
+                key,
+                widgets_rx,
+                state_tx,
+            ),
+        }
+    }
+
+    // An empty board gives way to widgets that show when idle, e.g. the clock
+    fn get_visibility(state: &ArrivalState, _now: DateTime<Utc>) -> Visibility {
+        match state.messages.is_empty() {
+            true => Visibility::WhenIdle,
+            false => Visibility::Visible,
+        }
+    }
+
+    fn has_content(state: &ArrivalState, _now: DateTime<Utc>) -> bool {
+        !state.messages.is_empty()
+    }
+
+    fn render(state: &ArrivalState, now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        let layout = BoardLayout::with_status_bar(canvas, state.status_bar.is_some());
+        let (messages, frame) = state.current_page(layout.rows, now);
+        render_arrival_display(messages, frame, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::firebase::fixture_config;
+
+    use super::*;
+
+    fn train(message: &str) -> SimpleArrivalDisplayable {
+        SimpleArrivalDisplayable::fixture(Line::RD, message, "3", "12")
+    }
+
+    fn sticky(message: &str) -> SimpleArrivalDisplayable {
+        SimpleArrivalDisplayable {
+            is_sticky: true,
+            ..SimpleArrivalDisplayable::fixture(Line::UNKNOWN, message, "", "")
+        }
+    }
+
+    fn paging(pin_sticky: bool) -> ArrivalPaging {
+        ArrivalPaging {
+            enabled: true,
+            interval_seconds: Some(10),
+            pin_sticky: Some(pin_sticky),
+        }
+    }
+
+    fn page(
+        messages: &[SimpleArrivalDisplayable],
+        paging: &ArrivalPaging,
+        rows: usize,
+        second: i64,
+    ) -> (Vec<String>, Option<PageIndicator>) {
+        let now = DateTime::from_timestamp(second, 0).unwrap();
+        let (rows, indicator) = paginate(messages, paging, rows, now);
+        (
+            rows.iter().map(|row| row.get_message()).collect(),
+            indicator,
+        )
+    }
+
+    #[test]
+    fn one_page_has_no_indicator() {
+        let messages = [train("A"), train("B")];
+        assert_eq!(
+            page(&messages, &paging(false), 4, 0),
+            (vec!["A".into(), "B".into()], None)
+        );
+    }
+
+    #[test]
+    fn pages_turn_every_interval() {
+        let messages: Vec<_> = ["A", "B", "C", "D", "E"].map(train).to_vec();
+        let (first, indicator) = page(&messages, &paging(false), 4, 0);
+        assert_eq!(first, ["A", "B", "C", "D"]);
+        assert_eq!(indicator, Some(PageIndicator { index: 0, count: 2 }));
+        let (second, indicator) = page(&messages, &paging(false), 4, 10);
+        assert_eq!(second, ["E"]);
+        assert_eq!(indicator, Some(PageIndicator { index: 1, count: 2 }));
+        assert_eq!(page(&messages, &paging(false), 4, 20).0, first);
+    }
+
+    #[test]
+    fn pinned_sticky_messages_are_on_every_page() {
+        let messages = vec![
+            train("A"),
+            sticky("Note"),
+            train("B"),
+            train("C"),
+            train("D"),
+        ];
+        let (first, indicator) = page(&messages, &paging(true), 3, 0);
+        assert_eq!(first, ["Note", "A", "B"]);
+        assert_eq!(indicator, Some(PageIndicator { index: 0, count: 2 }));
+        assert_eq!(page(&messages, &paging(true), 3, 10).0, ["Note", "C", "D"]);
+    }
+
+    #[test]
+    fn pinned_sticky_messages_leave_a_row_for_trains() {
+        let messages = vec![sticky("1"), sticky("2"), sticky("3"), train("A")];
+        let (first, indicator) = page(&messages, &paging(true), 2, 0);
+        assert_eq!(first, ["1", "2"]);
+        assert_eq!(indicator, Some(PageIndicator { index: 0, count: 3 }));
+        assert_eq!(page(&messages, &paging(true), 2, 20).0, ["1", "A"]);
+    }
+
+    fn fixture_widget(filters: serde_json::Value) -> ArrivalWidget {
+        fixture_config(json!({
+            "station_id": "A01",
+            "station_ids": ["C01"],
+            "filters": filters,
+        }))
+    }
+
+    async fn fixture_rows(widget: ArrivalWidget) -> Vec<(String, String, String)> {
+        let source = FilePredictionSource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/predictions.json"
+        ));
+        let state = get_latest_state(&source, &WmataClient::from_env(), widget)
+            .await
+            .unwrap();
+        state
+            .messages
+            .iter()
+            .map(|row| {
+                (
+                    row.get_line_label(),
+                    row.get_message(),
+                    row.get_arrival_time(),
+                )
+            })
+            .collect()
+    }
+
+    fn rows(rows: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+        rows.iter()
+            .map(|(line, message, arrival)| {
+                (line.to_string(), message.to_string(), arrival.to_string())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fixture_predictions_are_filtered_and_sorted() {
+        let widget = fixture_widget(json!({ "exclude_lines": ["BL"], "hide_no_passenger": true }));
+        assert_eq!(
+            fixture_rows(widget).await,
+            rows(&[
+                ("RD", "Shady Grv", "BRD"),
+                ("RD", "Glenmont", "3"),
+                ("OR", "Vienna", "5"),
+                ("SV", "Largo", "9"),
+                ("RD", "Shady Grv", "17"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn fixture_predictions_without_filters() {
+        let all = fixture_rows(fixture_widget(json!(null))).await;
+        assert_eq!(all.len(), 7);
+        assert_eq!(all[4], ("??".into(), "No Passenger".into(), "11".into()));
+        assert_eq!(all[5].0, "BL");
+    }
+
+    fn fares(current: f64, other: f64) -> Option<Fares> {
+        Some(Fares { current, other })
+    }
+
+    #[test]
+    fn header_shows_both_fares_when_they_fit() {
+        assert_eq!(get_header_left(fares(3.85, 2.45), 30), "$3.85 (2.45)");
+        assert_eq!(get_header_left(None, 30), "LN DEST");
+    }
+
+    // The 128x64 board has 12 columns before LV MIN
+    #[test]
+    fn header_keeps_a_space_before_the_column_headings() {
+        assert_eq!(get_header_left(fares(3.85, 2.45), 12), "$3.85");
+        assert_eq!(get_header_left(fares(3.85, 2.45), 13), "$3.85 (2.45)");
+        assert_eq!(get_header_left(fares(10.5, 6.75), 12), "$10.50");
+        assert_eq!(get_header_left(fares(10.5, 6.75), 5), "$10.");
+    }
+}
@@ -0,0 +1,532 @@
+use std::{collections::HashMap, error::Error, fmt::Debug, path::PathBuf, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, Point, Primitive},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+    Drawable, Pixel,
+};
+use log::{debug, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    firebase::{get_latest_widget, BikeshareWidget, TextOverflow, WidgetSnapshot},
+    fonts::BdfTextStyle,
+    led::ScreenCanvas,
+};
+
+use super::{
+    arrival::{draw_fitted_text, get_stale_header, get_stale_minutes, keep_last_state},
+    get_screen_size,
+    layout::BoardLayout,
+    registry::{Visibility, Widget, PRIORITY_BIKESHARE},
+};
+
+// GBFS feeds wrap everything in a data object, see https://github.com/MobilityData/gbfs
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GbfsResponse<T> {
+    data: T,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StationInformationData {
+    stations: Vec<StationInformation>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StationInformation {
+    station_id: String,
+    name: GbfsName,
+}
+
+// GBFS 3.0 gives names per language, earlier versions a plain string
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum GbfsName {
+    Plain(String),
+    Localized(Vec<LocalizedString>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LocalizedString {
+    text: String,
+}
+
+impl GbfsName {
+    fn get_text(&self) -> String {
+        match self {
+            GbfsName::Plain(text) => text.clone(),
+            GbfsName::Localized(names) => names
+                .first()
+                .map(|name| name.text.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StationStatusData {
+    stations: Vec<StationStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StationStatus {
+    station_id: String,
+    num_bikes_available: i64, // Includes e-bikes
+    num_ebikes_available: Option<i64>,
+    num_docks_available: Option<i64>,
+    is_renting: GbfsFlag,
+}
+
+// GBFS 1.x sends flags as 0/1
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum GbfsFlag {
+    Bool(bool),
+    Number(i64),
+}
+
+impl GbfsFlag {
+    fn is_set(&self) -> bool {
+        match self {
+            GbfsFlag::Bool(flag) => *flag,
+            GbfsFlag::Number(flag) => *flag != 0,
+        }
+    }
+}
+
+// Where the feed files come from. Anything that isn't a URL is read as a local directory.
+enum GbfsFeed {
+    Remote(String),
+    Local(PathBuf),
+}
+
+impl GbfsFeed {
+    fn new(feed: &str) -> Self {
+        match feed.starts_with("http://") || feed.starts_with("https://") {
+            true => GbfsFeed::Remote(feed.trim_end_matches('/').to_string()),
+            false => GbfsFeed::Local(PathBuf::from(feed)),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        file_name: &str,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        let raw_string = match self {
+            GbfsFeed::Remote(base_url) => {
+                reqwest::get(format!("{}/{}", base_url, file_name))
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await?
+            }
+            GbfsFeed::Local(directory) => {
+                tokio::fs::read_to_string(directory.join(file_name)).await?
+            }
+        };
+        let response: GbfsResponse<T> = serde_json::from_str(&raw_string)?;
+        Ok(response.data)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DockDisplayEntry {
+    pub name: String,
+    pub bikes: i64, // Classic bikes only
+    pub ebikes: i64,
+    pub docks: i64,
+    pub is_renting: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct BikeshareState {
+    pub visible: bool,
+    pub stations: Vec<DockDisplayEntry>,
+    pub last_update: DateTime<Utc>,
+    pub stale_after: TimeDelta,
+    pub text_overflow: TextOverflow,
+}
+
+impl BikeshareState {
+    pub fn hidden() -> Self {
+        BikeshareState {
+            visible: false,
+            stations: Vec::new(),
+            last_update: Utc::now(),
+            stale_after: TimeDelta::MAX,
+            text_overflow: TextOverflow::default(),
+        }
+    }
+}
+
+const STATION_INFORMATION_FILE: &str = "station_information.json";
+const STATION_STATUS_FILE: &str = "station_status.json";
+const DEFAULT_DISPLAY_SECONDS: u64 = 10;
+const DEFAULT_INTERVAL_SECONDS: u64 = 120;
+
+async fn get_docks(
+    widget: &BikeshareWidget,
+) -> Result<Vec<DockDisplayEntry>, Box<dyn Error + Send + Sync>> {
+    let feed = GbfsFeed::new(widget.get_feed());
+    let information: StationInformationData = feed.get(STATION_INFORMATION_FILE).await?;
+    let status: StationStatusData = feed.get(STATION_STATUS_FILE).await?;
+    let names: HashMap<String, String> = information
+        .stations
+        .into_iter()
+        .map(|station| (station.station_id, station.name.get_text()))
+        .collect();
+    let statuses: HashMap<String, StationStatus> = status
+        .stations
+        .into_iter()
+        .map(|station| (station.station_id.clone(), station))
+        .collect();
+
+    // In the configured order. Stations missing from the feed are skipped.
+    Ok(widget
+        .station_ids
+        .iter()
+        .filter_map(|station_id| {
+            let Some(status) = statuses.get(station_id) else {
+                warn!(target: "bikeshare_state_update", "Station {} is not in the feed", station_id);
+                return None;
+            };
+            let ebikes = status.num_ebikes_available.unwrap_or(0);
+            Some(DockDisplayEntry {
+                name: widget
+                    .names
+                    .as_ref()
+                    .and_then(|names| names.get(station_id))
+                    .or(names.get(station_id))
+                    .cloned()
+                    .unwrap_or(station_id.clone()),
+                bikes: (status.num_bikes_available - ebikes).max(0),
+                ebikes,
+                docks: status.num_docks_available.unwrap_or(0),
+                is_renting: status.is_renting.is_set(),
+            })
+        })
+        .collect())
+}
+
+pub fn spawn_bikeshare_update_task(
+    key: String,
+    widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<BikeshareState>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        tokio::time::sleep(Duration::from_secs(50)).await;
+        let mut last_state: Option<BikeshareState> = None;
+        loop {
+            debug!(target: "bikeshare_state_update", "Loading new state...");
+            let snapshot = widgets_rx.borrow().clone();
+            let Some(widget) = get_latest_widget::<BikeshareWidget>(snapshot, &key).await else {
+                debug!(target: "bikeshare_state_update", "No bikeshare widget configured.");
+                tokio::time::sleep(Duration::from_secs(DEFAULT_INTERVAL_SECONDS)).await;
+                continue;
+            };
+            let result = get_docks(&widget).await;
+            if let Some(stations) =
+                keep_last_state(result, "bikeshare_state_update", "Could not update docks")
+            {
+                info!(target: "bikeshare_state_update", "{} stations loaded. Sending to main thread.", stations.len());
+                last_state = Some(BikeshareState {
+                    visible: true,
+                    stations,
+                    last_update: Utc::now(),
+                    stale_after: TimeDelta::minutes(widget.get_stale_minutes()),
+                    text_overflow: widget.text_overflow.unwrap_or_default(),
+                });
+            }
+
+            if let Some(state) = last_state.clone() {
+                state_tx.send(state.clone()).unwrap();
+                tokio::time::sleep(Duration::from_secs(
+                    widget.display_seconds.unwrap_or(DEFAULT_DISPLAY_SECONDS),
+                ))
+                .await;
+                // Keep the docks around for a playlist
+                state_tx
+                    .send(BikeshareState {
+                        visible: false,
+                        ..state
+                    })
+                    .unwrap();
+            }
+            tokio::time::sleep(Duration::from_secs(
+                widget.interval_seconds.unwrap_or(DEFAULT_INTERVAL_SECONDS),
+            ))
+            .await;
+        }
+    })
+}
+
+// 16x9, in place of the line color bar and line name
+const BIKE_GLYPH: [&str; 9] = [
+    "    XXX     XXX ",
+    "     X      X   ",
+    "     XXXXXXXX   ",
+    "    XX     XX   ",
+    " XXX  X  XX XXX ",
+    "X   X X X  X X X",
+    "X XXXXXX   X X X",
+    "X   X      X   X",
+    " XXX        XXX ",
+];
+
+fn draw_bike_glyph<D>(top_left: Point, color: Rgb888, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let pixels = BIKE_GLYPH.iter().enumerate().flat_map(|(y, row)| {
+        row.chars()
+            .enumerate()
+            .filter(|(_, pixel)| *pixel == 'X')
+            .map(move |(x, _)| Pixel(top_left + Point::new(x as i32, y as i32), color))
+    });
+    canvas.draw_iter(pixels).unwrap();
+}
+
+// Empty counts are dimmed so the numbers that matter stand out
+fn draw_count<D>(count: i64, right: i32, baseline: i32, layout: &BoardLayout, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let color = match count {
+        0 => Rgb888::new(70, 70, 70),
+        _ => Rgb888::new(255, 255, 255),
+    };
+    Text::with_alignment(
+        &count.to_string(),
+        Point::new(right, baseline),
+        BdfTextStyle::new(layout.font, color),
+        Alignment::Right,
+    )
+    .draw(canvas)
+    .unwrap();
+}
+
+pub fn render_bikeshare_display<D>(state: BikeshareState, now: DateTime<Utc>, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let (screen_width, _) = get_screen_size(canvas);
+    let layout = BoardLayout::from_canvas(canvas);
+    let bike_color = Rgb888::new(230, 30, 40);
+    let gray_text_style = BdfTextStyle::new(layout.font, Rgb888::new(120, 120, 120));
+    let count_right = |column| get_count_right(column, screen_width, &layout);
+
+    // Header, replaced by a warning when the data is old
+    let header_position = Point::new(layout.get_header_x(0), layout.header_baseline);
+    match get_stale_minutes(state.last_update, state.stale_after, now) {
+        Some(minutes) => {
+            let (header, header_color) = get_stale_header(minutes);
+            Text::new(
+                &header,
+                header_position,
+                BdfTextStyle::new(layout.font, header_color),
+            )
+            .draw(canvas)
+            .unwrap();
+        }
+        None => {
+            Text::new("BIKES", header_position, gray_text_style)
+                .draw(canvas)
+                .unwrap();
+            for (column, label) in ["BK", "EB", "DK"].into_iter().enumerate() {
+                Text::with_alignment(
+                    label,
+                    Point::new(count_right(column), layout.header_baseline),
+                    gray_text_style,
+                    Alignment::Right,
+                )
+                .draw(canvas)
+                .unwrap();
+            }
+        }
+    }
+
+    // Line below header
+    Rectangle::with_corners(
+        Point::new(0, layout.divider_y),
+        Point::new(screen_width, layout.divider_y),
+    )
+    .into_styled(PrimitiveStyle::with_fill(Rgb888::new(40, 40, 40)))
+    .draw(canvas)
+    .unwrap();
+
+    // Names end where the BK heading starts
+    let name_width = count_right(0) - 2 * layout.get_char_width() - layout.destination_x;
+    for (index, station) in state.stations.iter().enumerate().take(layout.rows) {
+        let baseline = layout.get_row_baseline(index);
+        // The bike only fits rows as tall as it is, smaller fonts get the line color bar instead
+        let marker = layout.get_marker(index);
+        match marker.size.height as usize >= BIKE_GLYPH.len() {
+            true => draw_bike_glyph(
+                Point::new(1, baseline - BIKE_GLYPH.len() as i32),
+                bike_color,
+                canvas,
+            ),
+            false => marker
+                .into_styled(PrimitiveStyle::with_fill(bike_color))
+                .draw(canvas)
+                .unwrap(),
+        }
+
+        draw_fitted_text(
+            canvas,
+            &station.name,
+            Point::new(layout.destination_x, baseline),
+            name_width,
+            BdfTextStyle::new(layout.font, bike_color),
+            state.text_overflow,
+            now,
+        );
+
+        if !station.is_renting {
+            Text::with_alignment(
+                "CLOSED",
+                Point::new(screen_width, baseline),
+                BdfTextStyle::new(layout.font, Rgb888::new(255, 160, 0)),
+                Alignment::Right,
+            )
+            .draw(canvas)
+            .unwrap();
+            continue;
+        }
+        draw_count(station.bikes, count_right(0), baseline, &layout, canvas);
+        draw_count(station.ebikes, count_right(1), baseline, &layout, canvas);
+        draw_count(station.docks, count_right(2), baseline, &layout, canvas);
+    }
+}
+
+// Counts are right-aligned against the right edge in three columns, each two and a half
+// characters wide
+fn get_count_right(column: usize, screen_width: i32, layout: &BoardLayout) -> i32 {
+    screen_width - (2 - column as i32) * (layout.get_char_width() * 5 / 2)
+}
+
+// Bikes and docks at nearby stations, shown now and then
+pub struct BikeshareDisplay;
+
+impl Widget for BikeshareDisplay {
+    type Config = BikeshareWidget;
+    type State = BikeshareState;
+    const PRIORITY: i32 = PRIORITY_BIKESHARE;
+
+    fn initial_state() -> BikeshareState {
+        BikeshareState::hidden()
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<BikeshareState>,
+    ) -> JoinHandle<()> {
+        spawn_bikeshare_update_task(key, widgets_rx, state_tx)
+    }
+
+    fn get_visibility(state: &BikeshareState, _now: DateTime<Utc>) -> Visibility {
+        match state.visible {
+            true => Visibility::Visible,
+            false => Visibility::Hidden,
+        }
+    }
+
+    fn has_content(state: &BikeshareState, _now: DateTime<Utc>) -> bool {
+        !state.stations.is_empty()
+    }
+
+    fn render(state: &BikeshareState, now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        render_bikeshare_display(state.clone(), now, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::firebase::fixture_config;
+
+    use super::*;
+
+    const METRO_CENTER: &str = "08263a2b-1f3f-11e7-bf6b-3863bb334450";
+    const GALLERY_PLACE: &str = "082469cc-1f3f-11e7-bf6b-3863bb334450";
+
+    fn bikeshare_widget(station_ids: &[&str], names: serde_json::Value) -> BikeshareWidget {
+        fixture_config(json!({
+            "feed": concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/gbfs"),
+            "station_ids": station_ids,
+            "names": names,
+        }))
+    }
+
+    #[test]
+    fn names_are_plain_or_localized() {
+        let plain: GbfsName = serde_json::from_value(json!("Metro Center")).unwrap();
+        assert_eq!(plain.get_text(), "Metro Center");
+        let localized: GbfsName =
+            serde_json::from_value(json!([{ "text": "Metro Center", "language": "en" }])).unwrap();
+        assert_eq!(localized.get_text(), "Metro Center");
+    }
+
+    #[test]
+    fn flags_are_booleans_or_numbers() {
+        let flag = |value| serde_json::from_value::<GbfsFlag>(value).unwrap().is_set();
+        assert!(flag(json!(true)));
+        assert!(flag(json!(1)));
+        assert!(!flag(json!(false)));
+        assert!(!flag(json!(0)));
+    }
+
+    #[tokio::test]
+    async fn docks_come_from_a_local_feed() {
+        let names = json!({ GALLERY_PLACE: "Gallery" });
+        let docks = get_docks(&bikeshare_widget(
+            &[GALLERY_PLACE, "missing", METRO_CENTER],
+            names,
+        ))
+        .await
+        .unwrap();
+        let summary: Vec<_> = docks
+            .iter()
+            .map(|dock| {
+                (
+                    dock.name.as_str(),
+                    dock.bikes,
+                    dock.ebikes,
+                    dock.docks,
+                    dock.is_renting,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Gallery", 0, 0, 19, false),
+                ("Metro Center / 12th & G St NW", 5, 2, 27, true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_feed_files_are_errors() {
+        let mut widget = bikeshare_widget(&[METRO_CENTER], json!(null));
+        widget.feed = Some(String::from(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures"
+        )));
+        assert!(get_docks(&widget).await.is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use std::error::Error;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use embedded_graphics::pixelcolor::Rgb888;
+use futures::future::try_join_all;
+use itertools::Itertools;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    firebase::{get_latest_widget, get_widget_config, BusWidget, WidgetSnapshot},
+    led::ScreenCanvas,
+    wmata::WmataClient,
+};
+
+use super::{
+    arrival::{
+        get_leave_string, get_line_color, keep_last_state, render_arrival_display,
+        wait_for_next_update, ArrivalDisplayable, ArrivalState, Line, SimpleArrivalDisplayable,
+    },
+    layout::BoardLayout,
+    registry::{Visibility, Widget, PRIORITY_BOARD},
+};
+
+const BUS_PREDICTION_PATH: &str = "/NextBusService.svc/json/jPredictions";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BusPredictionApiReturn {
+    #[serde(rename(deserialize = "Predictions"))]
+    predictions: Vec<BusPrediction>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BusPrediction {
+    #[serde(rename(deserialize = "RouteID"))]
+    route_id: String,
+    #[serde(rename(deserialize = "DirectionText"))]
+    direction_text: String, // e.g. "East to Sibley Hospital"
+    #[serde(rename(deserialize = "Minutes"))]
+    minutes: i64,
+    #[serde(rename(deserialize = "TripID"))]
+    trip_id: String,
+}
+
+impl BusPrediction {
+    // The direction tells apart the stops on either side of the road, so it's kept with the
+    // compass point shortened, e.g. "East to Silver Spring" becomes "E Silver Spring"
+    fn get_destination(&self) -> String {
+        match self.direction_text.split_once(" to ") {
+            Some((direction, destination)) => format!(
+                "{} {}",
+                get_direction_abbreviation(direction.trim()),
+                destination.trim()
+            ),
+            None => self.direction_text.trim().to_string(),
+        }
+    }
+}
+
+fn get_direction_abbreviation(direction: &str) -> &str {
+    match direction.to_ascii_lowercase().as_str() {
+        "north" | "northbound" => "N",
+        "south" | "southbound" => "S",
+        "east" | "eastbound" => "E",
+        "west" | "westbound" => "W",
+        _ => direction, // e.g. "Loop"
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BusDisplayEntry {
+    route: String,
+    destination: String,
+    minutes: i64,
+    arrival_timestamp: DateTime<Utc>,
+    walk_time: TimeDelta,
+}
+
+impl BusDisplayEntry {
+    fn is_catchable(&self) -> bool {
+        self.arrival_timestamp - self.walk_time >= Utc::now()
+    }
+}
+
+impl ArrivalDisplayable for BusDisplayEntry {
+    fn get_comparison_timestamp(&self) -> DateTime<Utc> {
+        self.arrival_timestamp
+    }
+
+    fn get_comparison_timestamp_no_sticky(&self) -> DateTime<Utc> {
+        self.arrival_timestamp
+    }
+
+    fn get_message(&self) -> String {
+        self.destination.clone()
+    }
+
+    fn get_line(&self) -> Line {
+        Line::BUS
+    }
+
+    fn get_line_color(&self) -> Rgb888 {
+        get_line_color(Line::BUS)
+    }
+
+    fn get_line_label(&self) -> String {
+        self.route.clone()
+    }
+
+    fn pretty_print(&self) -> String {
+        format!("{} {} {}", self.route, self.destination, self.minutes)
+    }
+
+    fn get_leave(&self) -> String {
+        match self.minutes {
+            0 => "- ".to_string(),
+            _ => get_leave_string(self.arrival_timestamp, self.walk_time),
+        }
+    }
+
+    fn is_sticky(&self) -> bool {
+        false
+    }
+
+    fn get_arrival_time(&self) -> String {
+        match self.minutes {
+            0 => "DUE".to_string(),
+            minutes => minutes.to_string(),
+        }
+    }
+}
+
+async fn get_bus_predictions(
+    client: &WmataClient,
+    stop_id: &str,
+) -> Result<Vec<BusPrediction>, Box<dyn Error + Send + Sync>> {
+    let api_return: BusPredictionApiReturn = client
+        .get(BUS_PREDICTION_PATH, &[("StopID", stop_id)])
+        .await?;
+    Ok(api_return.predictions)
+}
+
+async fn get_latest_state(
+    client: &WmataClient,
+    widget: &BusWidget,
+) -> Result<Vec<SimpleArrivalDisplayable>, Box<dyn Error + Send + Sync>> {
+    let stop_ids = widget.get_stop_ids();
+    let predictions = try_join_all(
+        stop_ids
+            .iter()
+            .map(|stop_id| get_bus_predictions(client, stop_id)),
+    )
+    .await?;
+    let walk_time = TimeDelta::minutes(widget.get_walk_minutes());
+    Ok(predictions
+        .into_iter()
+        .flatten()
+        // A trip serving two of the configured stops is only shown once
+        .unique_by(|prediction| prediction.trip_id.clone())
+        .filter(|prediction| widget.wants_route(&prediction.route_id))
+        .map(|prediction| BusDisplayEntry {
+            destination: prediction.get_destination(),
+            arrival_timestamp: Utc::now() + TimeDelta::minutes(prediction.minutes),
+            route: prediction.route_id,
+            minutes: prediction.minutes,
+            walk_time,
+        })
+        .filter(|entry| !widget.should_hide_uncatchable() || entry.is_catchable())
+        .sorted_by_key(|entry| entry.arrival_timestamp)
+        .map(|entry| SimpleArrivalDisplayable::from_displayable(&entry))
+        .collect())
+}
+
+// Sends None while no bus widget is configured, so the rail board keeps the screen
+pub fn spawn_bus_update_task(
+    client: WmataClient,
+    key: String,
+    mut widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<Option<ArrivalState>>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        loop {
+            debug!(target: "bus_state_update", "Loading new state...");
+            let snapshot = widgets_rx.borrow_and_update().clone();
+            let config = get_widget_config(&snapshot, &key);
+            let Some(widget) = get_latest_widget::<BusWidget>(snapshot, &key).await else {
+                debug!(target: "bus_state_update", "No bus widget configured.");
+                state_tx.send(None).unwrap();
+                wait_for_next_update(&mut widgets_rx, &key, &config).await;
+                continue;
+            };
+            let result = get_latest_state(&client, &widget).await;
+            let Some(messages) = keep_last_state(
+                result,
+                "bus_state_update",
+                "Could not update bus predictions",
+            ) else {
+                wait_for_next_update(&mut widgets_rx, &key, &config).await;
+                continue;
+            };
+            let new_state = ArrivalState {
+                messages,
+                last_update: Utc::now(),
+                paging: widget.paging,
+                stale_after: TimeDelta::minutes(widget.get_stale_minutes()),
+                fares: None,
+                status_bar: None,
+                text_overflow: widget.text_overflow.unwrap_or_default(),
+            };
+            info!(target: "bus_state_update", "New state loaded. Sending to main thread.");
+            state_tx.send(Some(new_state)).unwrap();
+            wait_for_next_update(&mut widgets_rx, &key, &config).await;
+        }
+    })
+}
+
+// Bus arrivals, on the same board as rail. The two take turns.
+pub struct BusDisplay;
+
+impl Widget for BusDisplay {
+    type Config = BusWidget;
+    type State = Option<ArrivalState>;
+    const PRIORITY: i32 = PRIORITY_BOARD;
+
+    fn initial_state() -> Option<ArrivalState> {
+        None
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<Option<ArrivalState>>,
+    ) -> JoinHandle<()> {
+        spawn_bus_update_task(WmataClient::from_env(), key, widgets_rx, state_tx)
+    }
+
+    // Like the rail board, an empty board gives way to widgets that show when idle
+    fn get_visibility(state: &Option<ArrivalState>, _now: DateTime<Utc>) -> Visibility {
+        match state {
+            Some(state) if state.messages.is_empty() => Visibility::WhenIdle,
+            Some(_) => Visibility::Visible,
+            None => Visibility::Hidden,
+        }
+    }
+
+    fn has_content(state: &Option<ArrivalState>, _now: DateTime<Utc>) -> bool {
+        state
+            .as_ref()
+            .is_some_and(|state| !state.messages.is_empty())
+    }
+
+    fn render(state: &Option<ArrivalState>, now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        if let Some(state) = state {
+            let (messages, frame) = state.current_page(BoardLayout::from_canvas(canvas).rows, now);
+            render_arrival_display(messages, frame, canvas);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prediction(direction_text: &str) -> BusPrediction {
+        BusPrediction {
+            route_id: String::from("D6"),
+            direction_text: direction_text.to_string(),
+            minutes: 5,
+            trip_id: String::from("1"),
+        }
+    }
+
+    fn entry(minutes: i64) -> BusDisplayEntry {
+        BusDisplayEntry {
+            route: String::from("D6"),
+            destination: String::from("E Sibley Hospital"),
+            minutes,
+            arrival_timestamp: Utc::now() + TimeDelta::minutes(minutes),
+            walk_time: TimeDelta::minutes(3),
+        }
+    }
+
+    fn board(messages: Vec<SimpleArrivalDisplayable>) -> Option<ArrivalState> {
+        Some(ArrivalState {
+            messages,
+            ..ArrivalState::loading()
+        })
+    }
+
+    #[test]
+    fn destination_keeps_the_direction() {
+        assert_eq!(
+            prediction("East to Sibley Hospital").get_destination(),
+            "E Sibley Hospital"
+        );
+        assert_eq!(
+            prediction("Westbound to Federal Triangle").get_destination(),
+            "W Federal Triangle"
+        );
+        assert_eq!(
+            prediction("Loop to Union Station").get_destination(),
+            "Loop Union Station"
+        );
+        assert_eq!(prediction(" Downtown ").get_destination(), "Downtown");
+    }
+
+    #[test]
+    fn due_buses() {
+        assert_eq!(entry(0).get_arrival_time(), "DUE");
+        assert_eq!(entry(0).get_leave(), "- ");
+        assert_eq!(entry(12).get_arrival_time(), "12");
+        assert!(!entry(2).is_catchable());
+        assert!(entry(12).is_catchable());
+    }
+
+    #[test]
+    fn empty_board_shows_when_idle() {
+        let now = Utc::now();
+        assert_eq!(BusDisplay::get_visibility(&None, now), Visibility::Hidden);
+        assert!(!BusDisplay::has_content(&None, now));
+
+        let empty = board(vec![]);
+        assert_eq!(
+            BusDisplay::get_visibility(&empty, now),
+            Visibility::WhenIdle
+        );
+        assert!(!BusDisplay::has_content(&empty, now));
+
+        let buses = board(vec![SimpleArrivalDisplayable::from_displayable(&entry(12))]);
+        assert_eq!(BusDisplay::get_visibility(&buses, now), Visibility::Visible);
+        assert!(BusDisplay::has_content(&buses, now));
+    }
+}
@@ -0,0 +1,269 @@
+use std::{fmt::Debug, time::Duration};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_4X6, FONT_5X8, FONT_6X10, FONT_7X14},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, Point, RgbColor},
+    text::{Alignment, Text},
+    Drawable,
+};
+use log::debug;
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    firebase::{get_latest_widget, ClockSettings, ClockWidget, WidgetSnapshot},
+    led::ScreenCanvas,
+};
+
+use super::{
+    get_screen_size,
+    registry::{Visibility, Widget, PRIORITY_CLOCK},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClockFormat {
+    pub timezone: Tz,
+    pub use_24_hour: bool,
+}
+
+impl From<&ClockSettings> for ClockFormat {
+    fn from(settings: &ClockSettings) -> Self {
+        ClockFormat {
+            timezone: settings.get_timezone(),
+            use_24_hour: settings.is_24_hour(),
+        }
+    }
+}
+
+impl ClockFormat {
+    fn get_hours_minutes(&self, now: DateTime<Utc>) -> String {
+        let local = now.with_timezone(&self.timezone);
+        match self.use_24_hour {
+            true => local.format("%H:%M").to_string(),
+            false => local.format("%-I:%M").to_string(),
+        }
+    }
+
+    fn get_am_pm(&self, now: DateTime<Utc>) -> Option<String> {
+        (!self.use_24_hour).then(|| now.with_timezone(&self.timezone).format("%p").to_string())
+    }
+}
+
+// The date and time for the arrival board header
+#[derive(Clone, Copy, Debug)]
+pub struct StatusBar {
+    pub format: ClockFormat,
+    pub now: DateTime<Utc>,
+}
+
+impl StatusBar {
+    pub fn get_date(&self) -> String {
+        self.now
+            .with_timezone(&self.format.timezone)
+            .format("%a %m/%d")
+            .to_string()
+            .to_uppercase()
+    }
+
+    pub fn get_time(&self) -> String {
+        format!(
+            "{}{}",
+            self.format.get_hours_minutes(self.now),
+            self.format.get_am_pm(self.now).unwrap_or_default()
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClockState {
+    pub visible: bool,
+    pub show_when_idle: bool,
+    pub format: ClockFormat,
+}
+
+impl ClockState {
+    pub fn hidden() -> Self {
+        ClockState {
+            visible: false,
+            show_when_idle: false,
+            format: ClockFormat::from(&ClockSettings::default()),
+        }
+    }
+}
+
+// Largest first, as the fonts for the time and for AM/PM and the date
+static CLOCK_FONTS: [(&MonoFont, &MonoFont); 3] = [
+    (&FONT_10X20, &FONT_6X10),
+    (&FONT_7X14, &FONT_5X8),
+    (&FONT_5X8, &FONT_4X6),
+];
+
+const DEFAULT_DISPLAY_SECONDS: u64 = 10;
+const CONFIG_CHECK_SECONDS: u64 = 60;
+
+pub fn spawn_clock_update_task(
+    key: String,
+    mut widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<ClockState>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        loop {
+            let snapshot = widgets_rx.borrow_and_update().clone();
+            let Some(widget) = get_latest_widget::<ClockWidget>(snapshot, &key).await else {
+                debug!(target: "clock_state_update", "No clock widget configured.");
+                state_tx.send(ClockState::hidden()).unwrap();
+                wait_for_next_showing(&mut widgets_rx, CONFIG_CHECK_SECONDS).await;
+                continue;
+            };
+            let idle_state = ClockState {
+                visible: false,
+                show_when_idle: widget.should_show_when_idle(),
+                format: ClockFormat::from(&widget.clock),
+            };
+            let Some(interval_seconds) = widget.interval_seconds else {
+                state_tx.send(idle_state).unwrap();
+                wait_for_next_showing(&mut widgets_rx, CONFIG_CHECK_SECONDS).await;
+                continue;
+            };
+
+            state_tx
+                .send(ClockState {
+                    visible: true,
+                    ..idle_state
+                })
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(
+                widget.display_seconds.unwrap_or(DEFAULT_DISPLAY_SECONDS),
+            ))
+            .await;
+            state_tx.send(idle_state).unwrap();
+            wait_for_next_showing(&mut widgets_rx, interval_seconds).await;
+        }
+    })
+}
+
+// Sleeps until the clock is due again, or until its settings are edited
+async fn wait_for_next_showing(widgets_rx: &mut Receiver<Option<WidgetSnapshot>>, seconds: u64) {
+    tokio::select! {
+        _ = tokio::time::sleep(Duration::from_secs(seconds)) => {}
+        Ok(_) = widgets_rx.changed() => {
+            debug!(target: "clock_state_update", "Widget config changed");
+        }
+    }
+}
+
+pub fn render_clock_display<D>(state: ClockState, now: DateTime<Utc>, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let (screen_width, screen_height) = get_screen_size(canvas);
+    let center = screen_width / 2;
+    let time = state.format.get_hours_minutes(now);
+    let am_pm = state.format.get_am_pm(now);
+    let date = now
+        .with_timezone(&state.format.timezone)
+        .format("%a, %b %-d")
+        .to_string();
+
+    // The largest fonts the time and the date fit the screen in
+    let get_time_width = |(time_font, small_font): &(&MonoFont, &MonoFont)| {
+        time.len() as i32 * time_font.character_size.width as i32
+            + am_pm.as_ref().map_or(0, |am_pm| {
+                am_pm.len() as i32 * small_font.character_size.width as i32 + 2
+            })
+    };
+    let fonts = CLOCK_FONTS
+        .iter()
+        .find(|fonts| {
+            get_time_width(fonts) <= screen_width
+                && date.len() as i32 * fonts.1.character_size.width as i32 <= screen_width
+                && (fonts.0.character_size.height + fonts.1.character_size.height) as i32
+                    <= screen_height
+        })
+        .unwrap_or(&CLOCK_FONTS[CLOCK_FONTS.len() - 1]);
+    let (time_font, small_font) = *fonts;
+
+    // The date along the bottom, and the time centered in the space above it
+    let date_top = screen_height - small_font.character_size.height as i32;
+    let time_top = (date_top - time_font.character_size.height as i32) / 2;
+
+    // Time, with AM/PM in a smaller font after it. Both are centered together.
+    let time_baseline = time_top + time_font.baseline as i32;
+    let time_left = center - get_time_width(fonts) / 2;
+    Text::new(
+        &time,
+        Point::new(time_left, time_baseline),
+        MonoTextStyle::new(time_font, Rgb888::WHITE),
+    )
+    .draw(canvas)
+    .unwrap();
+    if let Some(am_pm) = am_pm {
+        Text::new(
+            &am_pm,
+            Point::new(
+                time_left + time.len() as i32 * time_font.character_size.width as i32 + 2,
+                time_baseline,
+            ),
+            MonoTextStyle::new(small_font, Rgb888::new(120, 120, 120)),
+        )
+        .draw(canvas)
+        .unwrap();
+    }
+
+    // Date
+    Text::with_alignment(
+        &date,
+        Point::new(center, date_top + small_font.baseline as i32),
+        MonoTextStyle::new(small_font, Rgb888::new(120, 120, 120)),
+        Alignment::Center,
+    )
+    .draw(canvas)
+    .unwrap();
+}
+
+// A full-screen clock, shown now and then or whenever the arrival board is empty
+pub struct ClockDisplay;
+
+impl Widget for ClockDisplay {
+    type Config = ClockWidget;
+    type State = ClockState;
+    const PRIORITY: i32 = PRIORITY_CLOCK;
+
+    fn initial_state() -> ClockState {
+        ClockState::hidden()
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<ClockState>,
+    ) -> JoinHandle<()> {
+        spawn_clock_update_task(key, widgets_rx, state_tx)
+    }
+
+    fn get_visibility(state: &ClockState, _now: DateTime<Utc>) -> Visibility {
+        match (state.visible, state.show_when_idle) {
+            (true, _) => Visibility::Visible,
+            (false, true) => Visibility::WhenIdle,
+            (false, false) => Visibility::Hidden,
+        }
+    }
+
+    fn has_content(_state: &ClockState, _now: DateTime<Utc>) -> bool {
+        true
+    }
+
+    fn render(state: &ClockState, now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        render_clock_display(*state, now, canvas);
+    }
+}
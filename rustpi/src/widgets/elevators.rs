@@ -0,0 +1,415 @@
+use std::{error::Error, fmt::Debug, time::Duration};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, Point, Primitive},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+    Drawable,
+};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    firebase::{
+        get_latest_widget, get_widget_config, wait_for_widget_edit, ElevatorWidget, WidgetSnapshot,
+    },
+    fonts::BdfTextStyle,
+    led::ScreenCanvas,
+    wmata::WmataClient,
+};
+
+use super::{
+    get_screen_size,
+    layout::BoardLayout,
+    registry::{Visibility, Widget, PRIORITY_ELEVATOR},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ElevatorIncidentApiReturn {
+    #[serde(rename(deserialize = "ElevatorIncidents"))]
+    incidents: Vec<ElevatorIncident>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ElevatorIncident {
+    #[serde(rename(deserialize = "UnitType"))]
+    unit_type: String, // "ELEVATOR" or "ESCALATOR"
+    #[serde(rename(deserialize = "StationCode"))]
+    station_code: String,
+    #[serde(rename(deserialize = "StationName"))]
+    station_name: String,
+    #[serde(rename(deserialize = "LocationDescription"))]
+    location_description: String,
+    #[serde(rename(deserialize = "EstimatedReturnToService"))]
+    estimated_return: Option<String>, // e.g. "2026-10-20T23:59:59"
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitType {
+    Elevator,
+    Escalator,
+}
+
+#[derive(Clone, Debug)]
+pub struct OutageDisplayEntry {
+    pub unit_type: UnitType,
+    pub station: String,
+    pub location: String,
+    pub estimated_return: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ElevatorState {
+    pub visible: bool,
+    pub station: Option<String>, // Shown in the header when the widget watches several stations
+    pub outages: Vec<OutageDisplayEntry>,
+    pub shown_at: DateTime<Utc>,
+    pub display_seconds: u64, // Outages that don't fit on one screen page through in this time
+}
+
+impl ElevatorState {
+    pub fn hidden() -> Self {
+        ElevatorState {
+            visible: false,
+            station: None,
+            outages: Vec::new(),
+            shown_at: Utc::now(),
+            display_seconds: DEFAULT_DISPLAY_SECONDS,
+        }
+    }
+
+    // The outages on screen now, `rows` at a time. Each page gets an equal share of the time.
+    fn get_page(&self, rows: usize, now: DateTime<Utc>) -> &[OutageDisplayEntry] {
+        let rows = rows.max(1);
+        let count = self.outages.len().div_ceil(rows).max(1);
+        let elapsed = (now - self.shown_at).num_milliseconds().max(0) as u64;
+        let index = (elapsed * count as u64 / (self.display_seconds.max(1) * 1000)) as usize;
+        self.outages
+            .chunks(rows)
+            .nth(index % count)
+            .unwrap_or_default()
+    }
+}
+
+const ELEVATOR_INCIDENTS_PATH: &str = "/Incidents.svc/json/ElevatorIncidents";
+const DEFAULT_DISPLAY_SECONDS: u64 = 8;
+const DEFAULT_INTERVAL_SECONDS: u64 = 180;
+const RETURN_COLUMNS: usize = 5; // e.g. 10/20
+
+fn get_unit_type(incident: &ElevatorIncident) -> UnitType {
+    if incident.unit_type.eq_ignore_ascii_case("ELEVATOR") {
+        UnitType::Elevator
+    } else {
+        UnitType::Escalator
+    }
+}
+
+fn get_return_string(estimated_return: &Option<String>) -> String {
+    estimated_return
+        .as_deref()
+        .and_then(|raw| NaiveDateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S").ok())
+        .map(|date| date.format("%m/%d").to_string())
+        .unwrap_or(String::from("  ?"))
+}
+
+async fn get_outages(
+    client: &WmataClient,
+    widget: &ElevatorWidget,
+) -> Result<Vec<OutageDisplayEntry>, Box<dyn Error + Send + Sync>> {
+    let station_codes = widget.get_station_codes();
+    let api_return: ElevatorIncidentApiReturn = client.get(ELEVATOR_INCIDENTS_PATH, &[]).await?;
+    Ok(api_return
+        .incidents
+        .iter()
+        .filter(|incident| station_codes.contains(&incident.station_code))
+        .filter(|incident| {
+            !widget.elevators_only.unwrap_or(false) || get_unit_type(incident) == UnitType::Elevator
+        })
+        .map(|incident| OutageDisplayEntry {
+            unit_type: get_unit_type(incident),
+            station: incident.station_name.clone(),
+            location: incident.location_description.clone(),
+            estimated_return: get_return_string(&incident.estimated_return),
+        })
+        .collect())
+}
+
+pub fn spawn_elevator_update_task(
+    client: WmataClient,
+    key: String,
+    mut widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<ElevatorState>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        loop {
+            debug!(target: "elevator_state_update", "Loading new state...");
+
+            let snapshot = widgets_rx.borrow_and_update().clone();
+            let config = get_widget_config(&snapshot, &key);
+            let Some(widget) = get_latest_widget::<ElevatorWidget>(snapshot, &key).await else {
+                warn!(target: "elevator_state_update", "Elevator widget is missing, not showing outages.");
+                wait_for_config_change(&mut widgets_rx, &key, &config, DEFAULT_INTERVAL_SECONDS)
+                    .await;
+                continue;
+            };
+            let mut outages = get_outages(&client, &widget).await.unwrap_or_else(|err| {
+                error!(target: "elevator_state_update", "Could not load elevator outages: {}", err);
+                Vec::new()
+            });
+            debug!(target: "elevator_state_update", "{:?}", outages);
+            info!(target: "elevator_state_update", "{} outages loaded. Sending to main thread.", outages.len());
+
+            // Several stations get turns of their own, with the station named in the header
+            outages.sort_by(|a, b| a.station.cmp(&b.station));
+            let several_stations = widget.get_station_codes().len() > 1;
+            let display_seconds = widget.display_seconds.unwrap_or(DEFAULT_DISPLAY_SECONDS);
+            let stations: Vec<ElevatorState> = outages
+                .chunk_by(|a, b| a.station == b.station)
+                .map(|station_outages| ElevatorState {
+                    visible: true,
+                    station: several_stations.then(|| station_outages[0].station.clone()),
+                    outages: station_outages.to_vec(),
+                    shown_at: Utc::now(),
+                    display_seconds,
+                })
+                .collect();
+
+            // Nothing is shown while every unit is working. Editing the widget starts over.
+            let mut edited = false;
+            for station in &stations {
+                state_tx
+                    .send(ElevatorState {
+                        shown_at: Utc::now(),
+                        ..station.clone()
+                    })
+                    .unwrap();
+                edited =
+                    wait_for_config_change(&mut widgets_rx, &key, &config, display_seconds).await;
+                if edited {
+                    break;
+                }
+            }
+            // Keep the first station around for a playlist
+            state_tx
+                .send(ElevatorState {
+                    visible: false,
+                    ..stations
+                        .into_iter()
+                        .next()
+                        .unwrap_or(ElevatorState::hidden())
+                })
+                .unwrap();
+            if !edited {
+                wait_for_config_change(
+                    &mut widgets_rx,
+                    &key,
+                    &config,
+                    widget.interval_seconds.unwrap_or(DEFAULT_INTERVAL_SECONDS),
+                )
+                .await;
+            }
+        }
+    })
+}
+
+// Sleeps for `seconds`, or until this widget's config is edited. True if it was edited.
+async fn wait_for_config_change(
+    widgets_rx: &mut Receiver<Option<WidgetSnapshot>>,
+    key: &str,
+    config: &Option<Value>,
+    seconds: u64,
+) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(Duration::from_secs(seconds)) => false,
+        _ = wait_for_widget_edit(widgets_rx, key, config) => {
+            debug!(target: "elevator_state_update", "Widget config changed");
+            true
+        }
+    }
+}
+
+fn get_unit_color(unit_type: UnitType) -> Rgb888 {
+    match unit_type {
+        UnitType::Elevator => Rgb888::new(0, 150, 255),
+        UnitType::Escalator => Rgb888::new(200, 200, 200),
+    }
+}
+
+fn get_unit_string(unit_type: UnitType) -> String {
+    match unit_type {
+        UnitType::Elevator => String::from("EL"),
+        UnitType::Escalator => String::from("ES"),
+    }
+}
+
+pub fn render_elevator_display<D>(state: ElevatorState, now: DateTime<Utc>, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let (screen_width, _) = get_screen_size(canvas);
+    let layout = BoardLayout::from_canvas(canvas);
+    let white_text_style = BdfTextStyle::new(layout.font, Rgb888::new(255, 255, 255));
+    // The estimated return lines up with the end of the header
+    let return_column = layout.header_columns.saturating_sub(RETURN_COLUMNS);
+    let return_x = layout.get_header_x(return_column);
+
+    // Header, which names the station when the widget watches several
+    let header = format!(
+        "   {:<width$.width$} BACK",
+        state.station.as_deref().unwrap_or("OUT OF SVC"),
+        width = return_column.saturating_sub(3)
+    );
+    Text::new(
+        &header,
+        Point::new(layout.get_header_x(0), layout.header_baseline),
+        BdfTextStyle::new(layout.font, Rgb888::new(120, 120, 120)),
+    )
+    .draw(canvas)
+    .unwrap();
+
+    // Line below header
+    Rectangle::with_corners(
+        Point::new(0, layout.divider_y),
+        Point::new(screen_width, layout.divider_y),
+    )
+    .into_styled(PrimitiveStyle::with_fill(Rgb888::new(40, 40, 40)))
+    .draw(canvas)
+    .unwrap();
+
+    // Locations are cut to leave a space before the date
+    let location_columns =
+        ((return_x - layout.destination_x) / layout.get_char_width() - 1).max(0) as usize;
+    for (index, outage) in state.get_page(layout.rows, now).iter().enumerate() {
+        let baseline = layout.get_row_baseline(index);
+        let color = get_unit_color(outage.unit_type);
+
+        // Draw left rectangle
+        layout
+            .get_marker(index)
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(canvas)
+            .unwrap();
+
+        // Draw unit type
+        Text::new(
+            &get_unit_string(outage.unit_type),
+            Point::new(layout.line_x, baseline),
+            BdfTextStyle::new(layout.font, color),
+        )
+        .draw(canvas)
+        .unwrap();
+
+        // Draw location
+        Text::new(
+            &outage
+                .location
+                .chars()
+                .take(location_columns)
+                .collect::<String>(),
+            Point::new(layout.destination_x, baseline),
+            BdfTextStyle::new(layout.font, color),
+        )
+        .draw(canvas)
+        .unwrap();
+
+        // Draw estimated return
+        Text::new(
+            &outage.estimated_return,
+            Point::new(return_x, baseline),
+            white_text_style,
+        )
+        .draw(canvas)
+        .unwrap();
+    }
+}
+
+// Elevator and escalator outages, shown now and then
+pub struct ElevatorDisplay;
+
+impl Widget for ElevatorDisplay {
+    type Config = ElevatorWidget;
+    type State = ElevatorState;
+    const PRIORITY: i32 = PRIORITY_ELEVATOR;
+
+    fn initial_state() -> ElevatorState {
+        ElevatorState::hidden()
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<ElevatorState>,
+    ) -> JoinHandle<()> {
+        spawn_elevator_update_task(WmataClient::from_env(), key, widgets_rx, state_tx)
+    }
+
+    fn get_visibility(state: &ElevatorState, _now: DateTime<Utc>) -> Visibility {
+        match state.visible {
+            true => Visibility::Visible,
+            false => Visibility::Hidden,
+        }
+    }
+
+    fn has_content(state: &ElevatorState, _now: DateTime<Utc>) -> bool {
+        !state.outages.is_empty()
+    }
+
+    fn render(state: &ElevatorState, now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        render_elevator_display(state.clone(), now, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn outage(location: &str) -> OutageDisplayEntry {
+        OutageDisplayEntry {
+            unit_type: UnitType::Escalator,
+            station: String::from("Metro Center"),
+            location: location.to_string(),
+            estimated_return: String::from("10/20"),
+        }
+    }
+
+    fn locations(state: &ElevatorState, rows: usize, seconds: i64) -> Vec<&str> {
+        state
+            .get_page(rows, state.shown_at + TimeDelta::seconds(seconds))
+            .iter()
+            .map(|outage| outage.location.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn pages_share_the_display_time() {
+        let state = ElevatorState {
+            visible: true,
+            outages: vec![outage("A"), outage("B"), outage("C")],
+            display_seconds: 8,
+            ..ElevatorState::hidden()
+        };
+        assert_eq!(locations(&state, 4, 0), ["A", "B", "C"]);
+        assert_eq!(locations(&state, 4, 7), ["A", "B", "C"]);
+        assert_eq!(locations(&state, 2, 0), ["A", "B"]);
+        assert_eq!(locations(&state, 2, 4), ["C"]);
+        // Shown again by a playlist after its turn
+        assert_eq!(locations(&state, 2, 8), ["A", "B"]);
+    }
+
+    #[test]
+    fn no_outages_is_an_empty_page() {
+        assert!(locations(&ElevatorState::hidden(), 4, 0).is_empty());
+    }
+}
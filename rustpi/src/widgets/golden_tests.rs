@@ -0,0 +1,587 @@
+// Pixel-for-pixel comparisons of the renderers against the reference images in tests/golden.
+// Run with `make test`, which uses the headless build so SDL2 isn't needed, and with
+// `make update_golden` to rewrite the references after an intentional change.
+// Mismatches write the actual frame and a diff image to target/golden-diff.
+
+use std::{env, fs, path::PathBuf};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, OriginDimensions, Point, RgbColor, Size},
+    text::{renderer::CharacterStyle, Baseline, Text},
+    Drawable, Pixel,
+};
+
+use crate::{
+    brightness::Brightness,
+    firebase::TextOverflow,
+    fonts::{BdfTextStyle, FONT_5X7, FONT_6X10, FONT_7X14},
+    framebuffer::FrameBuffer,
+    led::{DimmedCanvas, DEFAULT_SCREEN_HEIGHT, DEFAULT_SCREEN_WIDTH},
+};
+
+use super::{
+    alerts::{render_alert_display, AlertMode, AlertState},
+    arrival::{
+        render_arrival_display, ArrivalFrame, Line, PageIndicator, SimpleArrivalDisplayable,
+    },
+    bikeshare::{render_bikeshare_display, BikeshareState, DockDisplayEntry},
+    clock::{render_clock_display, ClockFormat, ClockState, StatusBar},
+    elevators::{render_elevator_display, ElevatorState, OutageDisplayEntry, UnitType},
+    line_map::{render_line_map_display, LineMapState, TrainMarker},
+    trip::Fares,
+    weather::{render_weather_display, Forecast, ForecastPeriod, WeatherIcon, WeatherState},
+};
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn diff_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden-diff")
+}
+
+fn blank_canvas() -> FrameBuffer {
+    FrameBuffer::new(Size::new(DEFAULT_SCREEN_WIDTH, DEFAULT_SCREEN_HEIGHT))
+}
+
+// Mismatched pixels in red over a dimmed copy of the reference
+fn diff_image(expected: &FrameBuffer, actual: &FrameBuffer) -> (FrameBuffer, usize) {
+    let size = actual.size();
+    let mut diff = FrameBuffer::new(size);
+    let mut mismatches = 0;
+    let pixels = (0..size.height as i32)
+        .flat_map(|y| (0..size.width as i32).map(move |x| Point::new(x, y)))
+        .map(|point| {
+            let want = expected.get_pixel(point).unwrap_or(Rgb888::BLACK);
+            let got = actual.get_pixel(point).unwrap_or(Rgb888::BLACK);
+            if want == got {
+                let gray = ((want.r() as u32 + want.g() as u32 + want.b() as u32) / 12) as u8;
+                Pixel(point, Rgb888::new(gray, gray, gray))
+            } else {
+                mismatches += 1;
+                Pixel(point, Rgb888::RED)
+            }
+        })
+        .collect::<Vec<_>>();
+    diff.draw_iter(pixels).unwrap();
+    (diff, mismatches)
+}
+
+fn assert_golden(name: &str, actual: &FrameBuffer) {
+    let reference = golden_dir().join(format!("{}.png", name));
+    if env::var("UPDATE_GOLDEN").is_ok() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        actual.save_png(&reference).unwrap();
+        return;
+    }
+
+    let expected = FrameBuffer::load_png(&reference).unwrap_or_else(|err| {
+        panic!(
+            "Could not load {} ({}). Run with UPDATE_GOLDEN=1 to create it.",
+            reference.display(),
+            err
+        )
+    });
+    if &expected == actual {
+        return;
+    }
+
+    let (diff, mismatches) = diff_image(&expected, actual);
+    fs::create_dir_all(diff_dir()).unwrap();
+    let actual_path = diff_dir().join(format!("{}.actual.png", name));
+    let diff_path = diff_dir().join(format!("{}.diff.png", name));
+    actual.save_png(&actual_path).unwrap();
+    diff.save_png(&diff_path).unwrap();
+    panic!(
+        "{} differs from {} in {} pixels. See {} and {}",
+        name,
+        reference.display(),
+        mismatches,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+fn render_arrivals(rows: Vec<SimpleArrivalDisplayable>, frame: ArrivalFrame) -> FrameBuffer {
+    let mut canvas = blank_canvas();
+    render_arrival_display(rows, frame, &mut canvas);
+    canvas
+}
+
+fn bus(route: &str, message: &str, leave: &str, arrival_time: &str) -> SimpleArrivalDisplayable {
+    SimpleArrivalDisplayable::fixture(Line::BUS, message, leave, arrival_time)
+        .with_line_label(route)
+}
+
+const INCIDENT: &str = "Red Line: Trains single tracking between Dupont Circle and Farragut North due to a disabled train.";
+
+const LONG_INCIDENT: &str = "Orange/Silver/Blue Line: Expect delays in both directions due to a track problem outside Stadium-Armory. Shuttle buses are replacing trains between Minnesota Ave and Largo. Customers may use Green Line service as an alternative.";
+
+fn render_alert(mode: AlertMode, message: &str, scroll_index: u32) -> FrameBuffer {
+    let mut canvas = blank_canvas();
+    render_alert_display(
+        AlertState::fixture(mode, message, scroll_index),
+        &mut canvas,
+    );
+    canvas
+}
+
+fn full_board() -> Vec<SimpleArrivalDisplayable> {
+    vec![
+        SimpleArrivalDisplayable::fixture(Line::RD, "Glenmont", "- ", "BRD"),
+        SimpleArrivalDisplayable::fixture(Line::OR, "Vienna", "- ", "4"),
+        SimpleArrivalDisplayable::fixture(Line::SV, "Ashburn", "3", "12"),
+        SimpleArrivalDisplayable::fixture(Line::BL, "Franconia", "10", "19"),
+        SimpleArrivalDisplayable::fixture(Line::GR, "Branch Av", "22", "31"),
+    ]
+}
+
+#[test]
+fn arrival_loading() {
+    let canvas = render_arrivals(
+        vec![SimpleArrivalDisplayable::loading()],
+        ArrivalFrame::default(),
+    );
+    assert_golden("arrival_loading", &canvas);
+}
+
+#[test]
+fn arrival_full_board() {
+    // The fifth row should be cut off
+    let canvas = render_arrivals(full_board(), ArrivalFrame::default());
+    assert_golden("arrival_full_board", &canvas);
+}
+
+// The board on other panel chains, in as many rows as fit
+fn render_arrivals_sized(width: u32, height: u32) -> FrameBuffer {
+    let mut canvas = FrameBuffer::new(Size::new(width, height));
+    render_arrival_display(full_board(), ArrivalFrame::default(), &mut canvas);
+    canvas
+}
+
+#[test]
+fn arrival_layout_64x32() {
+    assert_golden("arrival_layout_64x32", &render_arrivals_sized(64, 32));
+}
+
+#[test]
+fn arrival_layout_128x32() {
+    assert_golden("arrival_layout_128x32", &render_arrivals_sized(128, 32));
+}
+
+#[test]
+fn arrival_layout_192x64() {
+    assert_golden("arrival_layout_192x64", &render_arrivals_sized(192, 64));
+}
+
+#[test]
+fn arrival_layout_256x64() {
+    assert_golden("arrival_layout_256x64", &render_arrivals_sized(256, 64));
+}
+
+#[test]
+fn arrival_night_palette() {
+    let mut canvas = DimmedCanvas::new(blank_canvas());
+    canvas.set_brightness(Brightness {
+        percent: 40,
+        night_palette: true,
+    });
+    render_arrival_display(full_board(), ArrivalFrame::default(), &mut canvas);
+    assert_golden("arrival_night_palette", &canvas.into_inner());
+}
+
+#[test]
+fn arrival_mixed_lines() {
+    let rows = vec![
+        SimpleArrivalDisplayable::fixture(Line::YL, "Huntington", "- ", "ARR"),
+        SimpleArrivalDisplayable::fixture(Line::TS, "Lunch!", "5", "20"),
+        SimpleArrivalDisplayable::fixture(Line::UNKNOWN, "No Passenger", "- ", "7"),
+    ];
+    let canvas = render_arrivals(rows, ArrivalFrame::default());
+    assert_golden("arrival_mixed_lines", &canvas);
+}
+
+fn long_destinations() -> Vec<SimpleArrivalDisplayable> {
+    vec![
+        SimpleArrivalDisplayable::fixture(Line::SV, "Downtown Largo", "- ", "2"),
+        SimpleArrivalDisplayable::fixture(Line::OR, "New Carrollton", "3", "9"),
+        SimpleArrivalDisplayable::fixture(Line::TS, "Single tracking at Eastern Market", "", ""),
+        SimpleArrivalDisplayable::fixture(Line::RD, "Glenmont", "8", "14"),
+    ]
+}
+
+#[test]
+fn arrival_ellipsis() {
+    let frame = ArrivalFrame {
+        text_overflow: TextOverflow::Ellipsis,
+        ..Default::default()
+    };
+    let canvas = render_arrivals(long_destinations(), frame);
+    assert_golden("arrival_ellipsis", &canvas);
+}
+
+// Partway through scrolling, after the pause at the start
+#[test]
+fn arrival_marquee() {
+    let frame = ArrivalFrame {
+        text_overflow: TextOverflow::Marquee,
+        now: DateTime::from_timestamp_millis(3500).unwrap(),
+        ..Default::default()
+    };
+    let canvas = render_arrivals(long_destinations(), frame);
+    assert_golden("arrival_marquee", &canvas);
+}
+
+#[test]
+fn arrival_page_indicator() {
+    let frame = ArrivalFrame {
+        page: Some(PageIndicator { index: 1, count: 3 }),
+        ..Default::default()
+    };
+    let canvas = render_arrivals(full_board(), frame);
+    assert_golden("arrival_page_indicator", &canvas);
+}
+
+#[test]
+fn arrival_stale() {
+    let frame = ArrivalFrame {
+        page: Some(PageIndicator { index: 0, count: 2 }),
+        stale_minutes: Some(7),
+        ..Default::default()
+    };
+    let canvas = render_arrivals(full_board(), frame);
+    assert_golden("arrival_stale", &canvas);
+}
+
+// The second train comes after the morning peak
+fn render_trip_mode(mut canvas: FrameBuffer) -> FrameBuffer {
+    let rows = vec![
+        SimpleArrivalDisplayable::fixture(Line::RD, "09:21", "13", "17").with_fare(3.85),
+        SimpleArrivalDisplayable::fixture(Line::RD, "09:30", "22", "26").with_fare(2.45),
+    ];
+    let frame = ArrivalFrame {
+        page: Some(PageIndicator { index: 0, count: 2 }),
+        fares: Some(Fares {
+            current: 3.85,
+            other: 2.45,
+        }),
+        ..Default::default()
+    };
+    render_arrival_display(rows, frame, &mut canvas);
+    canvas
+}
+
+#[test]
+fn arrival_trip_mode() {
+    assert_golden("arrival_trip_mode", &render_trip_mode(blank_canvas()));
+}
+
+// Wide enough for both fares in the header
+#[test]
+fn arrival_trip_mode_256x64() {
+    let canvas = render_trip_mode(FrameBuffer::new(Size::new(256, 64)));
+    assert_golden("arrival_trip_mode_256x64", &canvas);
+}
+
+// Monday, 19:26 in Washington
+fn clock_time() -> DateTime<Utc> {
+    DateTime::from_timestamp(1792452360, 0).unwrap()
+}
+
+fn clock_format(use_24_hour: bool) -> ClockFormat {
+    ClockFormat {
+        timezone: chrono_tz::America::New_York,
+        use_24_hour,
+    }
+}
+
+#[test]
+fn arrival_status_bar() {
+    let frame = ArrivalFrame {
+        page: Some(PageIndicator { index: 0, count: 2 }),
+        status_bar: Some(StatusBar {
+            format: clock_format(false),
+            now: clock_time(),
+        }),
+        ..Default::default()
+    };
+    let canvas = render_arrivals(full_board(), frame);
+    assert_golden("arrival_status_bar", &canvas);
+}
+
+fn render_clock(use_24_hour: bool, mut canvas: FrameBuffer) -> FrameBuffer {
+    let state = ClockState {
+        visible: true,
+        show_when_idle: false,
+        format: clock_format(use_24_hour),
+    };
+    render_clock_display(state, clock_time(), &mut canvas);
+    canvas
+}
+
+#[test]
+fn clock_12_hour() {
+    assert_golden("clock_12_hour", &render_clock(false, blank_canvas()));
+}
+
+#[test]
+fn clock_24_hour() {
+    assert_golden("clock_24_hour", &render_clock(true, blank_canvas()));
+}
+
+#[test]
+fn clock_64x32() {
+    assert_golden(
+        "clock_64x32",
+        &render_clock(false, FrameBuffer::new(Size::new(64, 32))),
+    );
+}
+
+#[test]
+fn bus_board() {
+    let rows = vec![
+        bus("D6", "Sibley", "- ", "DUE"),
+        bus("30N", "Naylor Rd", "2", "5"),
+        bus("D6", "Farragut", "9", "12"),
+    ];
+    let canvas = render_arrivals(rows, ArrivalFrame::default());
+    assert_golden("bus_board", &canvas);
+}
+
+#[test]
+fn bus_long_headsigns() {
+    let rows = vec![
+        bus("D6", "E Sibley Hospital", "- ", "DUE"),
+        bus("30N", "W Naylor Rd Station", "2", "5"),
+        bus("D6", "W Farragut Square", "9", "12"),
+    ];
+    let frame = ArrivalFrame {
+        text_overflow: TextOverflow::Ellipsis,
+        ..Default::default()
+    };
+    let canvas = render_arrivals(rows, frame);
+    assert_golden("bus_long_headsigns", &canvas);
+}
+
+fn outages() -> ElevatorState {
+    let outage = |unit_type, location: &str, estimated_return: &str| OutageDisplayEntry {
+        unit_type,
+        station: String::from("Metro Center"),
+        location: location.to_string(),
+        estimated_return: estimated_return.to_string(),
+    };
+    ElevatorState {
+        visible: true,
+        station: None,
+        outages: vec![
+            outage(UnitType::Elevator, "Street to mezzanine", "10/20"),
+            outage(UnitType::Escalator, "Mezzanine to platform", "11/02"),
+            outage(UnitType::Elevator, "Platform", "  ?"),
+        ],
+        ..ElevatorState::hidden()
+    }
+}
+
+#[test]
+fn elevator_outages() {
+    let state = outages();
+    let mut canvas = blank_canvas();
+    render_elevator_display(state.clone(), state.shown_at, &mut canvas);
+    assert_golden("elevator_outages", &canvas);
+}
+
+// Two rows fit, so the third outage waits for the second page
+#[test]
+fn elevator_outages_128x32() {
+    let state = outages();
+    let mut canvas = FrameBuffer::new(Size::new(128, 32));
+    render_elevator_display(state.clone(), state.shown_at, &mut canvas);
+    assert_golden("elevator_outages_128x32", &canvas);
+}
+
+#[test]
+fn elevator_outages_by_station() {
+    let state = ElevatorState {
+        visible: true,
+        station: Some(String::from("Gallery Pl-Chinatown")),
+        outages: vec![OutageDisplayEntry {
+            unit_type: UnitType::Escalator,
+            station: String::from("Gallery Pl-Chinatown"),
+            location: String::from("Street to mezzanine"),
+            estimated_return: String::from("10/20"),
+        }],
+        ..ElevatorState::hidden()
+    };
+    let mut canvas = blank_canvas();
+    render_elevator_display(state.clone(), state.shown_at, &mut canvas);
+    assert_golden("elevator_outages_by_station", &canvas);
+}
+
+#[test]
+fn line_map() {
+    let train = |position, heading_right| TrainMarker {
+        position,
+        heading_right,
+    };
+    let state = LineMapState {
+        visible: true,
+        line: Line::RD,
+        station_count: 27,
+        highlighted_station: Some(14),
+        title: String::from("Metro Center"),
+        terminals: (String::from("Shady Grove"), String::from("Glenmont")),
+        trains: vec![
+            train(2.5, true),
+            train(11.0, true),
+            train(20.25, true),
+            train(6.0, false),
+            train(16.5, false),
+            train(26.0, false),
+        ],
+    };
+    let mut canvas = blank_canvas();
+    render_line_map_display(state, &mut canvas);
+    assert_golden("line_map", &canvas);
+}
+
+fn docks() -> BikeshareState {
+    let dock = |name: &str, bikes, ebikes, docks, is_renting| DockDisplayEntry {
+        name: String::from(name),
+        bikes,
+        ebikes,
+        docks,
+        is_renting,
+    };
+    BikeshareState {
+        visible: true,
+        stations: vec![
+            dock("Metro Center / 12th & G St NW", 7, 2, 14, true),
+            dock("Farragut North", 0, 1, 22, true),
+            dock("McPherson Sq", 12, 0, 0, true),
+            dock("Union Station", 0, 0, 0, false),
+        ],
+        last_update: clock_time(),
+        stale_after: TimeDelta::minutes(2),
+        text_overflow: TextOverflow::Ellipsis,
+    }
+}
+
+#[test]
+fn bikeshare_docks() {
+    let mut canvas = blank_canvas();
+    render_bikeshare_display(docks(), clock_time(), &mut canvas);
+    assert_golden("bikeshare_docks", &canvas);
+}
+
+// In the smaller font, with bars in place of the bikes
+#[test]
+fn bikeshare_docks_128x32() {
+    let mut canvas = FrameBuffer::new(Size::new(128, 32));
+    render_bikeshare_display(docks(), clock_time(), &mut canvas);
+    assert_golden("bikeshare_docks_128x32", &canvas);
+}
+
+fn render_weather(mut canvas: FrameBuffer) -> FrameBuffer {
+    let period = |label: &str, temperature, conditions: &str, icon| ForecastPeriod {
+        label: String::from(label),
+        temperature,
+        temperature_unit: String::from("F"),
+        conditions: String::from(conditions),
+        icon,
+    };
+    let state = WeatherState {
+        visible: true,
+        forecast: Some(Forecast {
+            current: period("SUN", 72, "Partly Sunny", WeatherIcon::PartlyCloudy),
+            upcoming: vec![
+                period("NGT", 58, "Chance Rain Showers", WeatherIcon::Rain),
+                period("MON", 64, "Thunderstorms", WeatherIcon::Thunder),
+                period("NGT", 31, "Light Snow", WeatherIcon::Snow),
+            ],
+        }),
+    };
+    render_weather_display(state, &mut canvas);
+    canvas
+}
+
+#[test]
+fn weather_forecast() {
+    assert_golden("weather_forecast", &render_weather(blank_canvas()));
+}
+
+// Only room for the current conditions
+#[test]
+fn weather_128x32() {
+    let canvas = render_weather(FrameBuffer::new(Size::new(128, 32)));
+    assert_golden("weather_128x32", &canvas);
+}
+
+// The small icon, with the forecast under it
+#[test]
+fn weather_96x48() {
+    let canvas = render_weather(FrameBuffer::new(Size::new(96, 48)));
+    assert_golden("weather_96x48", &canvas);
+}
+
+#[test]
+fn alert_border_phase_a() {
+    assert_golden(
+        "alert_border_phase_a",
+        &render_alert(AlertMode::MessageA, "", 0),
+    );
+}
+
+#[test]
+fn alert_border_phase_b() {
+    assert_golden(
+        "alert_border_phase_b",
+        &render_alert(AlertMode::MessageB, "", 0),
+    );
+}
+
+#[test]
+fn alert_intro() {
+    assert_golden("alert_intro", &render_alert(AlertMode::IntroA, "", 0));
+}
+
+#[test]
+fn alert_incident() {
+    assert_golden(
+        "alert_incident",
+        &render_alert(AlertMode::MessageA, INCIDENT, 0),
+    );
+}
+
+#[test]
+fn alert_long_incident_scrolled() {
+    assert_golden(
+        "alert_long_incident_scrolled",
+        &render_alert(AlertMode::MessageB, LONG_INCIDENT, 10),
+    );
+}
+
+#[test]
+fn bdf_fonts() {
+    let mut canvas = blank_canvas();
+    let mut y = 0;
+    for font in [&*FONT_5X7, &*FONT_6X10, &*FONT_7X14] {
+        Text::with_baseline(
+            "Shady Grove 12",
+            Point::new(1, y),
+            BdfTextStyle::new(font, Rgb888::WHITE),
+            Baseline::Top,
+        )
+        .draw(&mut canvas)
+        .unwrap();
+        y += font.get_line_height() as i32 + 2;
+    }
+    let mut highlighted = BdfTextStyle::new(&FONT_6X10, Rgb888::BLACK);
+    highlighted.set_background_color(Some(Rgb888::new(255, 160, 0)));
+    Text::new("Ashburn \u{e9}\u{2603}", Point::new(1, y + 8), highlighted)
+        .draw(&mut canvas)
+        .unwrap();
+    assert_golden("bdf_fonts", &canvas);
+}
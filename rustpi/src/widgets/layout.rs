@@ -0,0 +1,241 @@
+use std::sync::LazyLock;
+
+use embedded_graphics::{
+    geometry::Dimensions,
+    mono_font::{
+        ascii::{FONT_4X6, FONT_5X8, FONT_6X10},
+        MonoFont,
+    },
+    prelude::Point,
+    primitives::Rectangle,
+};
+
+use crate::fonts::{self, BdfFont};
+
+use super::get_screen_size;
+
+// A font the board can be drawn in, with the spacing that looks right for it
+struct BoardFont {
+    font: &'static LazyLock<BdfFont>,
+    row_pitch: i32,
+    label_font: &'static MonoFont<'static>, // Line labels longer than two characters, e.g. bus routes
+    small_font: &'static MonoFont<'static>, // The page indicator and the status bar
+}
+
+// Largest first, in the fonts the Python sign used. 7x14 is what the original 128x64 sign was
+// tuned for.
+static BOARD_FONTS: [BoardFont; 3] = [
+    BoardFont {
+        font: &fonts::FONT_7X14,
+        row_pitch: 12,
+        label_font: &FONT_5X8,
+        small_font: &FONT_6X10,
+    },
+    BoardFont {
+        font: &fonts::FONT_6X10,
+        row_pitch: 9,
+        label_font: &FONT_4X6,
+        small_font: &FONT_5X8,
+    },
+    BoardFont {
+        font: &fonts::FONT_5X7,
+        row_pitch: 7,
+        label_font: &FONT_4X6,
+        small_font: &FONT_4X6,
+    },
+];
+
+// Below this the board isn't worth reading, so a smaller font is tried
+const MIN_ROWS: usize = 2;
+const MIN_DESTINATION_COLUMNS: usize = 8;
+
+const MARKER_LEFT: i32 = 1;
+const MARKER_RIGHT: i32 = 2;
+const LINE_LEFT: i32 = 4;
+const LINE_COLUMNS: i32 = 2;
+const HEADER_LEFT: i32 = 1;
+const LEAVE_COLUMNS: usize = 3;
+const MINUTES_COLUMNS: usize = 3;
+
+// Where everything on the arrival board goes, worked out from the size of the canvas
+#[derive(Clone, Copy)]
+pub struct BoardLayout {
+    pub font: &'static BdfFont,
+    pub label_font: &'static MonoFont<'static>,
+    pub small_font: &'static MonoFont<'static>,
+    pub status_baseline: Option<i32>, // The status bar's own row, above the column headings
+    pub header_baseline: i32,
+    pub divider_y: i32,
+    pub header_columns: usize, // Characters across the header, which the columns below line up with
+    pub rows: usize,
+    pub line_x: i32,
+    pub destination_x: i32,
+    pub destination_columns: usize,
+    pub destination_width: i32, // In pixels, up to the LV or MIN column
+    pub leave_x: Option<i32>,   // No LV column when there's no room for it
+    pub minutes_x: i32,
+    row_pitch: i32,
+}
+
+impl BoardLayout {
+    pub fn from_canvas<D: Dimensions>(canvas: &D) -> Self {
+        BoardLayout::with_status_bar(canvas, false)
+    }
+
+    // With a row above the column headings for the status bar's time and date, if it's on
+    pub fn with_status_bar<D: Dimensions>(canvas: &D, status_bar: bool) -> Self {
+        let (width, height) = get_screen_size(canvas);
+        BOARD_FONTS
+            .iter()
+            .flat_map(|font| {
+                [true, false]
+                    .map(|show_leave| BoardLayout::new(font, show_leave, status_bar, width, height))
+            })
+            .find(|layout| {
+                layout.rows >= MIN_ROWS && layout.destination_columns >= MIN_DESTINATION_COLUMNS
+            })
+            // Too small for anything to fit well, so fit as much as possible
+            .unwrap_or_else(|| BoardLayout::new(&BOARD_FONTS[2], false, status_bar, width, height))
+    }
+
+    fn new(
+        board_font: &BoardFont,
+        show_leave: bool,
+        status_bar: bool,
+        width: i32,
+        height: i32,
+    ) -> Self {
+        let font: &'static BdfFont = board_font.font;
+        let char_width = font.get_column_width() as i32;
+        let descent = font.get_descent();
+        let row_pitch = board_font.row_pitch;
+        let status_height = match status_bar {
+            true => board_font.small_font.character_size.height as i32,
+            false => 0,
+        };
+        let divider_y = status_height + row_pitch;
+
+        let header_columns = ((width - HEADER_LEFT) / char_width).max(0) as usize;
+        let right_columns = match show_leave {
+            true => LEAVE_COLUMNS + MINUTES_COLUMNS,
+            false => MINUTES_COLUMNS,
+        };
+        let right_x = get_column_x(header_columns.saturating_sub(right_columns), char_width);
+        let destination_x = LINE_LEFT + LINE_COLUMNS * char_width + char_width / 2 + 1;
+
+        // Rows go as far down as their descenders still fit
+        let rows = (0..)
+            .take_while(|row| divider_y + row_pitch * (row + 1) + descent < height)
+            .count();
+
+        BoardLayout {
+            font,
+            label_font: board_font.label_font,
+            small_font: board_font.small_font,
+            status_baseline: status_bar.then_some(board_font.small_font.baseline as i32),
+            header_baseline: divider_y - 2,
+            divider_y,
+            header_columns,
+            rows,
+            line_x: LINE_LEFT,
+            destination_x,
+            destination_columns: ((right_x - destination_x) / char_width).max(0) as usize,
+            destination_width: (right_x - destination_x).max(0),
+            leave_x: show_leave.then_some(right_x),
+            minutes_x: match show_leave {
+                true => right_x + LEAVE_COLUMNS as i32 * char_width,
+                false => right_x,
+            },
+            row_pitch,
+        }
+    }
+
+    pub fn get_char_width(&self) -> i32 {
+        self.font.get_column_width() as i32
+    }
+
+    // x of a character in the header
+    pub fn get_header_x(&self, column: usize) -> i32 {
+        get_column_x(column, self.get_char_width())
+    }
+
+    pub fn get_row_baseline(&self, row: usize) -> i32 {
+        self.divider_y + self.row_pitch * (row as i32 + 1)
+    }
+
+    // The bar in the line's color at the start of a row
+    pub fn get_marker(&self, row: usize) -> Rectangle {
+        let baseline = self.get_row_baseline(row);
+        Rectangle::with_corners(
+            Point::new(MARKER_LEFT, baseline),
+            Point::new(MARKER_RIGHT, baseline - (self.row_pitch - 2)),
+        )
+    }
+}
+
+fn get_column_x(column: usize, char_width: i32) -> i32 {
+    HEADER_LEFT + column as i32 * char_width
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::prelude::Size;
+
+    use crate::framebuffer::FrameBuffer;
+
+    use super::*;
+
+    fn layout(width: u32, height: u32) -> BoardLayout {
+        BoardLayout::from_canvas(&FrameBuffer::new(Size::new(width, height)))
+    }
+
+    // Where the original sign drew everything at 128x64
+    #[test]
+    fn matches_the_original_sign() {
+        let layout = layout(128, 64);
+        assert_eq!(layout.get_marker(0).top_left.x, 1);
+        assert_eq!(layout.line_x, 4);
+        assert_eq!(layout.destination_x, 22);
+        assert_eq!(layout.leave_x, Some(85));
+        assert_eq!(layout.minutes_x, 106);
+        assert_eq!(layout.rows, 4);
+        assert_eq!(layout.get_row_baseline(0), 24);
+    }
+
+    #[test]
+    fn wider_panels_get_longer_destinations() {
+        let narrow = layout(128, 64);
+        let wide = layout(256, 64);
+        assert_eq!(wide.destination_x, narrow.destination_x);
+        assert!(wide.destination_columns > narrow.destination_columns);
+        assert_eq!(wide.rows, narrow.rows);
+    }
+
+    #[test]
+    fn small_panels_drop_the_leave_column_and_shrink_the_font() {
+        let layout = layout(96, 48);
+        assert_eq!(layout.get_char_width(), 6);
+        assert_eq!(layout.leave_x, None);
+        assert!(layout.rows >= MIN_ROWS);
+        assert!(layout.destination_columns >= MIN_DESTINATION_COLUMNS);
+    }
+
+    #[test]
+    fn status_bar_takes_a_row_from_the_trains() {
+        let canvas = FrameBuffer::new(Size::new(128, 64));
+        let plain = BoardLayout::from_canvas(&canvas);
+        let layout = BoardLayout::with_status_bar(&canvas, true);
+        assert_eq!(plain.status_baseline, None);
+        assert_eq!(layout.status_baseline, Some(7));
+        assert_eq!(layout.divider_y, plain.divider_y + 10);
+        assert_eq!(layout.leave_x, plain.leave_x);
+        assert_eq!(layout.rows, 3);
+    }
+
+    #[test]
+    fn tiny_panels_still_get_a_layout() {
+        let layout = layout(16, 8);
+        assert_eq!(layout.leave_x, None);
+        assert_eq!(layout.destination_width, 0);
+    }
+}
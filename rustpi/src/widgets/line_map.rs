@@ -0,0 +1,563 @@
+use std::{collections::HashMap, error::Error, fmt::Debug, time::Duration};
+
+use cached::proc_macro::cached;
+use chrono::{DateTime, Utc};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_5X8, FONT_6X10},
+        MonoTextStyle,
+    },
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, Point, Primitive, RgbColor, Size},
+    primitives::{Line as LineSegment, PrimitiveStyle, Rectangle, Triangle},
+    text::{Alignment, Text},
+    Drawable,
+};
+use itertools::Itertools;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    firebase::{get_latest_widget, LineMapWidget, WidgetSnapshot},
+    led::ScreenCanvas,
+    wmata::WmataClient,
+};
+
+use super::{
+    arrival::{get_line_color, get_line_string, get_string_line, Line},
+    get_screen_size,
+    registry::{Visibility, Widget, PRIORITY_LINE_MAP},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrainPositionApiReturn {
+    #[serde(rename(deserialize = "TrainPositions"))]
+    train_positions: Vec<TrainPosition>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrainPosition {
+    #[serde(rename(deserialize = "CircuitId"))]
+    circuit_id: i64,
+    #[serde(rename(deserialize = "LineCode"))]
+    line_code: Option<String>, // Missing for trains not in passenger service
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StandardRouteApiReturn {
+    #[serde(rename(deserialize = "StandardRoutes"))]
+    standard_routes: Vec<StandardRoute>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StandardRoute {
+    #[serde(rename(deserialize = "LineCode"))]
+    line_code: String,
+    #[serde(rename(deserialize = "TrackNum"))]
+    track_num: i64,
+    #[serde(rename(deserialize = "TrackCircuits"))]
+    track_circuits: Vec<TrackCircuit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrackCircuit {
+    #[serde(rename(deserialize = "SeqNum"))]
+    seq_num: i64,
+    #[serde(rename(deserialize = "CircuitId"))]
+    circuit_id: i64,
+    #[serde(rename(deserialize = "StationCode"))]
+    station_code: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StationListApiReturn {
+    #[serde(rename(deserialize = "Stations"))]
+    stations: Vec<Station>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Station {
+    #[serde(rename(deserialize = "Code"))]
+    code: String,
+    #[serde(rename(deserialize = "Name"))]
+    name: String,
+}
+
+// Where a circuit sits on the strip, measured in stations from the left end
+#[derive(Clone, Copy, Debug)]
+struct CircuitPosition {
+    position: f32,
+    heading_right: bool,
+}
+
+// One line's stations in strip order, and every circuit on either track placed between them
+#[derive(Clone, Debug)]
+struct LineRoute {
+    stations: Vec<String>,
+    station_names: HashMap<String, String>,
+    circuits: HashMap<i64, CircuitPosition>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TrainMarker {
+    pub position: f32,
+    pub heading_right: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct LineMapState {
+    pub visible: bool,
+    pub line: Line,
+    pub station_count: usize,
+    pub highlighted_station: Option<usize>,
+    pub title: String,
+    pub terminals: (String, String),
+    pub trains: Vec<TrainMarker>,
+}
+
+impl LineMapState {
+    pub fn hidden() -> Self {
+        LineMapState {
+            visible: false,
+            line: Line::UNKNOWN,
+            station_count: 0,
+            highlighted_station: None,
+            title: String::new(),
+            terminals: (String::new(), String::new()),
+            trains: Vec::new(),
+        }
+    }
+}
+
+const TRAIN_POSITIONS_PATH: &str = "/TrainPositions/TrainPositions";
+const STANDARD_ROUTES_PATH: &str = "/TrainPositions/StandardRoutes";
+const STATION_LIST_PATH: &str = "/Rail.svc/json/jStations";
+const DEFAULT_DISPLAY_SECONDS: u64 = 20;
+const DEFAULT_INTERVAL_SECONDS: u64 = 120;
+const POSITION_REFRESH_SECONDS: u64 = 10; // WMATA refreshes positions every 7-10 seconds
+
+// Places every circuit of a track between the stations on either side of it
+fn place_track_circuits(
+    circuits: &[TrackCircuit],
+    station_index: &HashMap<&str, usize>,
+) -> Vec<(i64, CircuitPosition)> {
+    let stops: Vec<(usize, f32)> = circuits
+        .iter()
+        .enumerate()
+        .filter_map(|(seq, circuit)| {
+            let index = station_index.get(circuit.station_code.as_deref()?)?;
+            Some((seq, *index as f32))
+        })
+        .collect();
+    let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+        return Vec::new();
+    };
+    let heading_right = last.1 > first.1;
+    circuits
+        .iter()
+        .enumerate()
+        .map(|(seq, circuit)| {
+            let previous = stops.iter().rev().find(|(stop, _)| *stop <= seq);
+            let next = stops.iter().find(|(stop, _)| *stop >= seq);
+            let position = match (previous, next) {
+                (Some(previous), Some(next)) if next.0 != previous.0 => {
+                    let progress = (seq - previous.0) as f32 / (next.0 - previous.0) as f32;
+                    previous.1 + (next.1 - previous.1) * progress
+                }
+                (Some(stop), _) | (_, Some(stop)) => stop.1,
+                (None, None) => unreachable!(),
+            };
+            (
+                circuit.circuit_id,
+                CircuitPosition {
+                    position,
+                    heading_right,
+                },
+            )
+        })
+        .collect()
+}
+
+// Routes only change with the timetable, so they're loaded once a day
+#[cached(
+    time = 86400,
+    key = "String",
+    convert = r#"{ line.to_string() }"#,
+    result = true
+)]
+async fn get_line_route(
+    client: &WmataClient,
+    line: &str,
+) -> Result<LineRoute, Box<dyn Error + Send + Sync>> {
+    let api_return: StandardRouteApiReturn = client
+        .get(STANDARD_ROUTES_PATH, &[("contentType", "json")])
+        .await?;
+    let tracks: Vec<Vec<TrackCircuit>> = api_return
+        .standard_routes
+        .into_iter()
+        .filter(|route| route.line_code == line)
+        .sorted_by_key(|route| route.track_num)
+        .map(|route| {
+            route
+                .track_circuits
+                .into_iter()
+                .sorted_by_key(|circuit| circuit.seq_num)
+                .collect()
+        })
+        .collect();
+    // The first track sets the order of the stations on the strip
+    let stations: Vec<String> = tracks
+        .first()
+        .ok_or(format!("No standard route for line {}", line))?
+        .iter()
+        .filter_map(|circuit| circuit.station_code.clone())
+        .unique()
+        .collect();
+    let station_index: HashMap<&str, usize> = stations
+        .iter()
+        .enumerate()
+        .map(|(index, code)| (code.as_str(), index))
+        .collect();
+    let circuits = tracks
+        .iter()
+        .flat_map(|track| place_track_circuits(track, &station_index))
+        .collect();
+
+    let station_list: StationListApiReturn =
+        client.get(STATION_LIST_PATH, &[("LineCode", line)]).await?;
+    let station_names = station_list
+        .stations
+        .into_iter()
+        .map(|station| (station.code, station.name))
+        .collect();
+
+    Ok(LineRoute {
+        stations,
+        station_names,
+        circuits,
+    })
+}
+
+async fn get_line_map(
+    client: &WmataClient,
+    widget: &LineMapWidget,
+) -> Result<LineMapState, Box<dyn Error + Send + Sync>> {
+    let line = widget.line.trim().to_uppercase();
+    let station_id = widget.station_id.trim().to_uppercase();
+    let route = get_line_route(client, &line).await?;
+    let api_return: TrainPositionApiReturn = client
+        .get(TRAIN_POSITIONS_PATH, &[("contentType", "json")])
+        .await?;
+    let trains = api_return
+        .train_positions
+        .iter()
+        .filter(|train| train.line_code.as_deref() == Some(line.as_str()))
+        .filter_map(|train| route.circuits.get(&train.circuit_id))
+        .map(|circuit| TrainMarker {
+            position: circuit.position,
+            heading_right: circuit.heading_right,
+        })
+        .collect();
+    let get_name = |code: &String| {
+        route
+            .station_names
+            .get(code)
+            .cloned()
+            .unwrap_or(code.clone())
+    };
+
+    Ok(LineMapState {
+        visible: true,
+        line: get_string_line(&line),
+        station_count: route.stations.len(),
+        highlighted_station: route.stations.iter().position(|code| code == &station_id),
+        title: route
+            .station_names
+            .get(&station_id)
+            .cloned()
+            .unwrap_or(station_id),
+        terminals: (
+            route.stations.first().map(get_name).unwrap_or_default(),
+            route.stations.last().map(get_name).unwrap_or_default(),
+        ),
+        trains,
+    })
+}
+
+pub fn spawn_line_map_update_task(
+    client: WmataClient,
+    key: String,
+    widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<LineMapState>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        loop {
+            debug!(target: "line_map_state_update", "Loading new state...");
+            let snapshot = widgets_rx.borrow().clone();
+            let Some(widget) = get_latest_widget::<LineMapWidget>(snapshot, &key).await else {
+                debug!(target: "line_map_state_update", "No line map widget configured.");
+                tokio::time::sleep(Duration::from_secs(DEFAULT_INTERVAL_SECONDS)).await;
+                continue;
+            };
+
+            // Trains keep moving while the map is up
+            let shown_until = Instant::now()
+                + Duration::from_secs(widget.display_seconds.unwrap_or(DEFAULT_DISPLAY_SECONDS));
+            while Instant::now() < shown_until {
+                match get_line_map(&client, &widget).await {
+                    Ok(state) => {
+                        info!(target: "line_map_state_update", "{} trains on the map. Sending to main thread.", state.trains.len());
+                        state_tx.send(state).unwrap();
+                    }
+                    Err(err) => {
+                        error!(target: "line_map_state_update", "Could not load the line map: {}", err);
+                        break;
+                    }
+                }
+                tokio::time::sleep_until(
+                    shown_until.min(Instant::now() + Duration::from_secs(POSITION_REFRESH_SECONDS)),
+                )
+                .await;
+            }
+            state_tx.send(LineMapState::hidden()).unwrap();
+            tokio::time::sleep(Duration::from_secs(
+                widget.interval_seconds.unwrap_or(DEFAULT_INTERVAL_SECONDS),
+            ))
+            .await;
+        }
+    })
+}
+
+const STRIP_LEFT: i32 = 4;
+const STRIP_RIGHT_MARGIN: i32 = 5;
+const STRIP_Y: i32 = 32;
+const TERMINAL_WIDTH: usize = 12; // Characters per terminal name, so both fit side by side
+
+fn get_strip_x(position: f32, station_count: usize, strip_right: i32) -> i32 {
+    if station_count < 2 {
+        return STRIP_LEFT;
+    }
+    let spacing = (strip_right - STRIP_LEFT) as f32 / (station_count - 1) as f32;
+    STRIP_LEFT + (position * spacing).round() as i32
+}
+
+pub fn render_line_map_display<D>(state: LineMapState, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let (screen_width, screen_height) = get_screen_size(canvas);
+    let strip_right = screen_width - STRIP_RIGHT_MARGIN;
+    let line_color = get_line_color(state.line);
+    let gray_text_style = MonoTextStyle::new(&FONT_5X8, Rgb888::new(120, 120, 120));
+
+    // Header: line and our station
+    Text::new(
+        &get_line_string(state.line),
+        Point::new(1, 8),
+        MonoTextStyle::new(&FONT_6X10, line_color),
+    )
+    .draw(canvas)
+    .unwrap();
+    Text::new(
+        &state.title.chars().take(18).collect::<String>(),
+        Point::new(17, 8),
+        MonoTextStyle::new(&FONT_6X10, Rgb888::WHITE),
+    )
+    .draw(canvas)
+    .unwrap();
+
+    // Track between the end stations
+    LineSegment::new(
+        Point::new(STRIP_LEFT, STRIP_Y),
+        Point::new(strip_right, STRIP_Y),
+    )
+    .into_styled(PrimitiveStyle::with_stroke(
+        Rgb888::new(line_color.r() / 3, line_color.g() / 3, line_color.b() / 3),
+        1,
+    ))
+    .draw(canvas)
+    .unwrap();
+
+    // Station dots, ours drawn bigger and in white
+    for index in 0..state.station_count {
+        let x = get_strip_x(index as f32, state.station_count, strip_right);
+        let (size, color) = if state.highlighted_station == Some(index) {
+            (5, Rgb888::WHITE)
+        } else {
+            (3, line_color)
+        };
+        Rectangle::with_center(Point::new(x, STRIP_Y), Size::new(size, size))
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(canvas)
+            .unwrap();
+    }
+
+    // Trains as arrows pointing the way they're going, above the strip heading right and below heading left
+    for train in &state.trains {
+        let x = get_strip_x(train.position, state.station_count, strip_right);
+        let (y, direction) = if train.heading_right {
+            (STRIP_Y - 7, 1)
+        } else {
+            (STRIP_Y + 7, -1)
+        };
+        Triangle::new(
+            Point::new(x - 2 * direction, y - 2),
+            Point::new(x - 2 * direction, y + 2),
+            Point::new(x + 2 * direction, y),
+        )
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::WHITE))
+        .draw(canvas)
+        .unwrap();
+    }
+
+    // End stations
+    Text::new(
+        &state
+            .terminals
+            .0
+            .chars()
+            .take(TERMINAL_WIDTH)
+            .collect::<String>(),
+        Point::new(1, screen_height - 2),
+        gray_text_style,
+    )
+    .draw(canvas)
+    .unwrap();
+    Text::with_alignment(
+        &state
+            .terminals
+            .1
+            .chars()
+            .take(TERMINAL_WIDTH)
+            .collect::<String>(),
+        Point::new(screen_width - 1, screen_height - 2),
+        gray_text_style,
+        Alignment::Right,
+    )
+    .draw(canvas)
+    .unwrap();
+}
+
+// Live train positions along one line, shown now and then
+pub struct LineMapDisplay;
+
+impl Widget for LineMapDisplay {
+    type Config = LineMapWidget;
+    type State = LineMapState;
+    const PRIORITY: i32 = PRIORITY_LINE_MAP;
+
+    fn initial_state() -> LineMapState {
+        LineMapState::hidden()
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<LineMapState>,
+    ) -> JoinHandle<()> {
+        spawn_line_map_update_task(WmataClient::from_env(), key, widgets_rx, state_tx)
+    }
+
+    fn get_visibility(state: &LineMapState, _now: DateTime<Utc>) -> Visibility {
+        match state.visible {
+            true => Visibility::Visible,
+            false => Visibility::Hidden,
+        }
+    }
+
+    fn render(state: &LineMapState, _now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        render_line_map_display(state.clone(), canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circuit(circuit_id: i64, station_code: Option<&str>) -> TrackCircuit {
+        TrackCircuit {
+            seq_num: circuit_id,
+            circuit_id,
+            station_code: station_code.map(String::from),
+        }
+    }
+
+    fn positions(circuits: &[TrackCircuit]) -> Vec<(i64, f32, bool)> {
+        let station_index = HashMap::from([("A01", 0), ("A02", 1), ("A03", 2)]);
+        place_track_circuits(circuits, &station_index)
+            .into_iter()
+            .map(|(id, placed)| (id, placed.position, placed.heading_right))
+            .collect()
+    }
+
+    #[test]
+    fn circuits_between_stations_are_spread_evenly() {
+        let circuits = [
+            circuit(10, Some("A01")),
+            circuit(11, None),
+            circuit(12, None),
+            circuit(13, None),
+            circuit(14, Some("A02")),
+            circuit(15, None),
+            circuit(16, Some("A03")),
+        ];
+        assert_eq!(
+            positions(&circuits),
+            [
+                (10, 0.0, true),
+                (11, 0.25, true),
+                (12, 0.5, true),
+                (13, 0.75, true),
+                (14, 1.0, true),
+                (15, 1.5, true),
+                (16, 2.0, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_other_track_heads_left() {
+        let circuits = [
+            circuit(20, Some("A03")),
+            circuit(21, None),
+            circuit(22, Some("A01")),
+        ];
+        assert_eq!(
+            positions(&circuits),
+            [(20, 2.0, false), (21, 1.0, false), (22, 0.0, false)]
+        );
+    }
+
+    #[test]
+    fn circuits_past_the_end_stations_stay_at_them() {
+        let circuits = [
+            circuit(30, None),
+            circuit(31, Some("A01")),
+            circuit(32, Some("A02")),
+            circuit(33, None),
+        ];
+        assert_eq!(
+            positions(&circuits),
+            [
+                (30, 0.0, true),
+                (31, 0.0, true),
+                (32, 1.0, true),
+                (33, 1.0, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn tracks_without_known_stations_are_left_out() {
+        let circuits = [circuit(40, Some("Z99")), circuit(41, None)];
+        assert!(positions(&circuits).is_empty());
+    }
+}
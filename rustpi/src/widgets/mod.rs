@@ -1,7 +1,22 @@
+use embedded_graphics::geometry::Dimensions;
+
 pub mod alerts;
 pub mod arrival;
-pub const LINE_HEIGHT: i32 = 10;
-pub const LINE_HEIGHT_WITH_PADDING: i32 = 12;
-pub const MAX_LINES: usize = 4;
-pub const SCREEN_WIDTH: u32 = 64 * 2;
-pub const SCREEN_HEIGHT: u32 = 32 * 2;
+pub mod bikeshare;
+pub mod bus;
+pub mod clock;
+pub mod elevators;
+#[cfg(test)]
+mod golden_tests;
+pub mod layout;
+pub mod line_map;
+pub mod playlist;
+pub mod registry;
+pub mod trip;
+pub mod weather;
+
+// The canvas is whatever size the panels add up to, so renderers measure it rather than assume one
+pub fn get_screen_size<D: Dimensions>(canvas: &D) -> (i32, i32) {
+    let size = canvas.bounding_box().size;
+    (size.width as i32, size.height as i32)
+}
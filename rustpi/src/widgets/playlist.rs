@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::firebase::{get_latest_widget, PlaylistEntry, ScreenPlaylist, WidgetSnapshot};
+
+const RELOAD_SECONDS: u64 = 60;
+
+pub fn spawn_playlist_update_task(
+    key: String,
+    mut widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    playlist_tx: Sender<Option<ScreenPlaylist>>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        loop {
+            let snapshot = widgets_rx.borrow_and_update().clone();
+            let playlist = get_latest_widget::<ScreenPlaylist>(snapshot, &key)
+                .await
+                .map(|mut playlist| {
+                    playlist.validate();
+                    playlist
+                });
+            debug!(target: "playlist_update", "Loaded playlist {}: {:?}", key, playlist);
+            // Only a changed playlist starts over with a new entry
+            playlist_tx.send_if_modified(|current| {
+                let modified = *current != playlist;
+                *current = playlist;
+                modified
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(RELOAD_SECONDS)) => {},
+                _ = widgets_rx.changed() => {},
+            }
+        }
+    })
+}
+
+// Which playlist entry is on screen, and until when
+#[derive(Default)]
+pub struct PlaylistScheduler {
+    current: Option<(usize, DateTime<Utc>)>,
+}
+
+impl PlaylistScheduler {
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    // The index of the entry to show now. `is_available` tells whether an entry's widget has
+    // anything to show; entries without content are skipped, and cut short if it runs out.
+    pub fn get_entry(
+        &mut self,
+        playlist: &ScreenPlaylist,
+        now: DateTime<Utc>,
+        is_available: impl Fn(&PlaylistEntry) -> bool,
+    ) -> Option<usize> {
+        if let Some((index, until)) = self.current {
+            if now < until && playlist.entries.get(index).is_some_and(&is_available) {
+                return Some(index);
+            }
+        }
+        let previous = self.current.map(|(index, _)| index);
+        self.current = None;
+
+        let local = now.with_timezone(&playlist.get_timezone()).naive_local();
+        let eligible: Vec<usize> = playlist
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.get_weight() > 0 && entry.is_scheduled(local) && is_available(entry)
+            })
+            .map(|(index, _)| index)
+            .collect();
+        // Don't show the same entry twice in a row when there's something else
+        let choices: Vec<usize> = if eligible.len() > 1 {
+            eligible
+                .into_iter()
+                .filter(|index| Some(*index) != previous)
+                .collect()
+        } else {
+            eligible
+        };
+
+        let total: u32 = choices
+            .iter()
+            .map(|index| playlist.entries[*index].get_weight())
+            .sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = rand::random_range(0..total);
+        for index in choices {
+            let entry = &playlist.entries[index];
+            if pick < entry.get_weight() {
+                debug!(target: "playlist_update", "Showing {} for {}", entry.widget, entry.get_duration());
+                self.current = Some((index, now + entry.get_duration()));
+                return Some(index);
+            }
+            pick -= entry.get_weight();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use serde_json::json;
+
+    use crate::firebase::fixture_config;
+
+    use super::*;
+
+    fn playlist(entries: serde_json::Value) -> ScreenPlaylist {
+        fixture_config(json!({ "entries": entries }))
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 1, 6, 17, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn picks_entries_by_weight() {
+        let playlist = playlist(json!([
+            { "widget": "clock", "weight": 3 },
+            { "widget": "weather" },
+            { "widget": "elevators", "weight": 0 },
+        ]));
+        let mut scheduler = PlaylistScheduler::default();
+        let mut counts = [0; 3];
+        for _ in 0..4000 {
+            scheduler.reset();
+            let index = scheduler.get_entry(&playlist, start(), |_| true).unwrap();
+            counts[index] += 1;
+        }
+        assert!((2700..3300).contains(&counts[0]), "{:?}", counts);
+        assert_eq!(counts[2], 0);
+    }
+
+    #[test]
+    fn keeps_an_entry_for_its_duration() {
+        let playlist = playlist(json!([
+            { "widget": "clock", "seconds": 10 },
+            { "widget": "weather", "seconds": 10 },
+        ]));
+        let mut scheduler = PlaylistScheduler::default();
+        let first = scheduler.get_entry(&playlist, start(), |_| true);
+        let later = start() + TimeDelta::seconds(9);
+        assert_eq!(scheduler.get_entry(&playlist, later, |_| true), first);
+        // Unless its widget runs out of things to show
+        let index = first.unwrap();
+        let widget = &playlist.entries[index].widget;
+        let next = scheduler.get_entry(&playlist, later, |entry| &entry.widget != widget);
+        assert_eq!(next, Some(1 - index));
+    }
+
+    #[test]
+    fn doesnt_repeat_an_entry_when_there_is_another() {
+        let playlist = playlist(json!([
+            { "widget": "clock", "seconds": 10, "weight": 100 },
+            { "widget": "weather", "seconds": 10 },
+        ]));
+        let mut scheduler = PlaylistScheduler::default();
+        let mut previous = None;
+        for step in 0..20 {
+            let now = start() + TimeDelta::seconds(10 * step);
+            let index = scheduler.get_entry(&playlist, now, |_| true);
+            assert_ne!(index, previous);
+            previous = index;
+        }
+    }
+
+    #[test]
+    fn repeats_the_only_eligible_entry() {
+        let playlist = playlist(json!([
+            { "widget": "clock", "seconds": 10 },
+            { "widget": "weather", "windows": [{ "start": "06:00", "end": "09:00" }] },
+        ]));
+        let mut scheduler = PlaylistScheduler::default();
+        for step in 0..3 {
+            let now = start() + TimeDelta::seconds(10 * step);
+            assert_eq!(scheduler.get_entry(&playlist, now, |_| true), Some(0));
+        }
+        assert_eq!(scheduler.get_entry(&playlist, start(), |_| false), None);
+    }
+}
@@ -0,0 +1,568 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use tokio::{
+    sync::watch::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    firebase::{
+        find_settings_key, BrightnessSettings, LoadableWidget, PlaylistEntry, ScreenPlaylist,
+        WidgetList, WidgetSnapshot,
+    },
+    led::ScreenCanvas,
+};
+
+use super::{
+    alerts::AlertDisplay,
+    arrival::ArrivalDisplay,
+    bikeshare::BikeshareDisplay,
+    bus::BusDisplay,
+    clock::ClockDisplay,
+    elevators::ElevatorDisplay,
+    line_map::LineMapDisplay,
+    playlist::{spawn_playlist_update_task, PlaylistScheduler},
+    weather::WeatherDisplay,
+};
+
+// Higher priorities win. Alerts take over everything else, even a playlist.
+pub const PRIORITY_ALERT: i32 = 100;
+pub const PRIORITY_ELEVATOR: i32 = 80;
+pub const PRIORITY_LINE_MAP: i32 = 70;
+pub const PRIORITY_WEATHER: i32 = 60;
+pub const PRIORITY_BIKESHARE: i32 = 50;
+pub const PRIORITY_CLOCK: i32 = 40;
+pub const PRIORITY_BOARD: i32 = 10; // Rail and bus arrivals
+
+// Widgets of the same priority that want the screen take turns
+const ROTATION_SECONDS: i64 = 15;
+
+// Documents that configure the sign rather than a widget
+const SETTINGS_NAMES: [&str; 2] = [ScreenPlaylist::NAME, BrightnessSettings::NAME];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Hidden,
+    WhenIdle, // Only while no widget has anything to show, e.g. an empty arrival board
+    Visible,
+}
+
+// A kind of widget: the Firebase document that configures it, the task that keeps its state
+// current and how that state is drawn.
+pub trait Widget: 'static {
+    type Config: LoadableWidget;
+    type State: Clone + Send + Sync + 'static;
+    const PRIORITY: i32;
+
+    fn initial_state() -> Self::State;
+    // `key` is the Firebase push key of this instance's config
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<Self::State>,
+    ) -> JoinHandle<()>;
+    fn get_visibility(state: &Self::State, now: DateTime<Utc>) -> Visibility;
+    // Whether a playlist can show this widget now, even outside its own display times
+    fn has_content(state: &Self::State, now: DateTime<Utc>) -> bool {
+        Self::get_visibility(state, now) != Visibility::Hidden
+    }
+    fn render(state: &Self::State, now: DateTime<Utc>, canvas: &mut ScreenCanvas);
+}
+
+// One configured widget, with its update task running
+trait WidgetInstance {
+    fn get_name(&self) -> &'static str;
+    fn get_priority(&self) -> i32;
+    fn get_visibility(&self, now: DateTime<Utc>) -> Visibility;
+    fn has_content(&self, now: DateTime<Utc>) -> bool;
+    fn render(&self, now: DateTime<Utc>, canvas: &mut ScreenCanvas);
+}
+
+struct RunningWidget<W: Widget> {
+    state_rx: Receiver<W::State>,
+    task: JoinHandle<()>,
+}
+
+impl<W: Widget> WidgetInstance for RunningWidget<W> {
+    fn get_name(&self) -> &'static str {
+        W::Config::NAME
+    }
+
+    fn get_priority(&self) -> i32 {
+        W::PRIORITY
+    }
+
+    fn get_visibility(&self, now: DateTime<Utc>) -> Visibility {
+        W::get_visibility(&self.state_rx.borrow(), now)
+    }
+
+    fn has_content(&self, now: DateTime<Utc>) -> bool {
+        W::has_content(&self.state_rx.borrow(), now)
+    }
+
+    fn render(&self, now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        let state = self.state_rx.borrow().clone();
+        W::render(&state, now, canvas);
+    }
+}
+
+impl<W: Widget> Drop for RunningWidget<W> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// The playlist document, reloaded by its own task
+struct RunningPlaylist {
+    key: String,
+    playlist_rx: Receiver<Option<ScreenPlaylist>>,
+    task: JoinHandle<()>,
+}
+
+impl RunningPlaylist {
+    fn start(key: String, widgets_rx: Receiver<Option<WidgetSnapshot>>) -> Self {
+        let (playlist_tx, playlist_rx) = watch::channel(None);
+        RunningPlaylist {
+            task: spawn_playlist_update_task(key.clone(), widgets_rx, playlist_tx),
+            key,
+            playlist_rx,
+        }
+    }
+}
+
+impl Drop for RunningPlaylist {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+type WidgetFactory = fn(String, Receiver<Option<WidgetSnapshot>>) -> Box<dyn WidgetInstance>;
+
+fn start_widget<W: Widget>(
+    key: String,
+    widgets_rx: Receiver<Option<WidgetSnapshot>>,
+) -> Box<dyn WidgetInstance> {
+    let (state_tx, state_rx) = watch::channel(W::initial_state());
+    Box::new(RunningWidget::<W> {
+        state_rx,
+        task: W::spawn_update_task(key, widgets_rx, state_tx),
+    })
+}
+
+// Starts an instance of each widget configured in Firebase, looked up by its `name`, and picks
+// which one is on screen: by the playlist if there is one, otherwise by priority.
+pub struct WidgetRegistry {
+    factories: HashMap<&'static str, WidgetFactory>,
+    instances: BTreeMap<String, Box<dyn WidgetInstance>>, // By push key
+    playlist: Option<RunningPlaylist>,
+    scheduler: PlaylistScheduler,
+    widgets_rx: Receiver<Option<WidgetSnapshot>>,
+}
+
+impl WidgetRegistry {
+    pub fn new(widgets_rx: Receiver<Option<WidgetSnapshot>>) -> Self {
+        WidgetRegistry {
+            factories: HashMap::new(),
+            instances: BTreeMap::new(),
+            playlist: None,
+            scheduler: PlaylistScheduler::default(),
+            widgets_rx,
+        }
+    }
+
+    // Every widget the sign knows how to show. New widgets only need a line here.
+    pub fn with_builtin_widgets(widgets_rx: Receiver<Option<WidgetSnapshot>>) -> Self {
+        let mut registry = WidgetRegistry::new(widgets_rx);
+        registry.register::<AlertDisplay>();
+        registry.register::<ArrivalDisplay>();
+        registry.register::<BikeshareDisplay>();
+        registry.register::<BusDisplay>();
+        registry.register::<ClockDisplay>();
+        registry.register::<ElevatorDisplay>();
+        registry.register::<LineMapDisplay>();
+        registry.register::<WeatherDisplay>();
+        registry
+    }
+
+    pub fn register<W: Widget>(&mut self) {
+        self.factories.insert(W::Config::NAME, start_widget::<W>);
+    }
+
+    // Starts widgets that were added and stops the ones that were removed or changed type
+    pub fn sync(&mut self, list: &WidgetList) {
+        self.sync_playlist(list);
+        self.instances.retain(|key, instance| match list.get(key) {
+            Some(name) if name == instance.get_name() => true,
+            _ => {
+                info!(target: "widget_registry", "Stopping {} ({})", instance.get_name(), key);
+                false
+            }
+        });
+        for (key, name) in list {
+            if self.instances.contains_key(key) || SETTINGS_NAMES.contains(&name.as_str()) {
+                continue;
+            }
+            let Some(factory) = self.factories.get(name.as_str()) else {
+                warn!(target: "widget_registry", "Unknown widget {} ({})", name, key);
+                continue;
+            };
+            info!(target: "widget_registry", "Starting {} ({})", name, key);
+            self.instances
+                .insert(key.clone(), factory(key.clone(), self.widgets_rx.clone()));
+        }
+    }
+
+    fn sync_playlist(&mut self, list: &WidgetList) {
+        let key = find_settings_key::<ScreenPlaylist>(list);
+        if self.playlist.as_ref().map(|playlist| &playlist.key) == key.as_ref() {
+            return;
+        }
+        if let Some(playlist) = &self.playlist {
+            info!(target: "widget_registry", "Stopping playlist ({})", playlist.key);
+        }
+        self.scheduler.reset();
+        self.playlist = key.map(|key| {
+            info!(target: "widget_registry", "Starting playlist ({})", key);
+            RunningPlaylist::start(key, self.widgets_rx.clone())
+        });
+    }
+
+    // Draws the widget that should be on screen now. False if none of them have anything to show.
+    pub fn render(&mut self, now: DateTime<Utc>, canvas: &mut ScreenCanvas) -> bool {
+        let Some(key) = self
+            .choose_takeover(now)
+            .or_else(|| self.choose_from_playlist(now))
+            .or_else(|| self.choose_by_priority(now))
+        else {
+            return false;
+        };
+        self.instances[&key].render(now, canvas);
+        true
+    }
+
+    fn choose_takeover(&self, now: DateTime<Utc>) -> Option<String> {
+        self.instances
+            .iter()
+            .filter(|(_, instance)| instance.get_priority() >= PRIORITY_ALERT)
+            .find(|(_, instance)| instance.get_visibility(now) == Visibility::Visible)
+            .map(|(key, _)| key.clone())
+    }
+
+    fn choose_from_playlist(&mut self, now: DateTime<Utc>) -> Option<String> {
+        let running = self.playlist.as_mut()?;
+        if running.playlist_rx.has_changed().unwrap_or(false) {
+            self.scheduler.reset();
+        }
+        let playlist = running.playlist_rx.borrow_and_update().clone()?;
+        let instances = &self.instances;
+        let index = self.scheduler.get_entry(&playlist, now, |entry| {
+            find_entry_instance(instances, entry, now).is_some()
+        })?;
+        find_entry_instance(instances, &playlist.entries[index], now)
+    }
+
+    // With a playlist, the widgets it schedules stay off screen outside their entries, so only
+    // the ones it doesn't mention and idle widgets like the clock are left
+    fn choose_by_priority(&self, now: DateTime<Utc>) -> Option<String> {
+        let playlist = self
+            .playlist
+            .as_ref()
+            .and_then(|running| running.playlist_rx.borrow().clone());
+        let visibilities: Vec<(&String, &dyn WidgetInstance, Visibility)> = self
+            .instances
+            .iter()
+            .map(|(key, instance)| {
+                let scheduled = playlist.as_ref().is_some_and(|playlist| {
+                    playlist
+                        .entries
+                        .iter()
+                        .any(|entry| is_entry_instance(entry, key, instance.as_ref()))
+                });
+                let visibility = match instance.get_visibility(now) {
+                    Visibility::Visible if scheduled => Visibility::Hidden,
+                    visibility => visibility,
+                };
+                (key, instance.as_ref(), visibility)
+            })
+            .collect();
+        choose_instance(&visibilities, Visibility::Visible, now)
+            .or_else(|| choose_instance(&visibilities, Visibility::WhenIdle, now))
+    }
+}
+
+// An entry names either one instance by push key, or any instance of a widget
+fn find_entry_instance(
+    instances: &BTreeMap<String, Box<dyn WidgetInstance>>,
+    entry: &PlaylistEntry,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    instances
+        .iter()
+        .filter(|(key, instance)| is_entry_instance(entry, key, instance.as_ref()))
+        .find(|(_, instance)| instance.has_content(now))
+        .map(|(key, _)| key.clone())
+}
+
+fn is_entry_instance(entry: &PlaylistEntry, key: &str, instance: &dyn WidgetInstance) -> bool {
+    key == entry.widget || instance.get_name() == entry.widget
+}
+
+fn choose_instance(
+    visibilities: &[(&String, &dyn WidgetInstance, Visibility)],
+    wanted: Visibility,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    let candidates: Vec<(&String, &dyn WidgetInstance)> = visibilities
+        .iter()
+        .filter(|(_, _, visibility)| *visibility == wanted)
+        .map(|(key, instance, _)| (*key, *instance))
+        .collect();
+    let priority = candidates
+        .iter()
+        .map(|(_, instance)| instance.get_priority())
+        .max()?;
+    let top: Vec<&String> = candidates
+        .into_iter()
+        .filter(|(_, instance)| instance.get_priority() == priority)
+        .map(|(key, _)| key)
+        .collect();
+    let turn = (now.timestamp() / ROTATION_SECONDS) as usize % top.len();
+    Some(top[turn].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::pending, marker::PhantomData};
+
+    use chrono::TimeZone;
+    use tokio::spawn;
+
+    use crate::{
+        firebase::{ClockWidget, ElevatorWidget},
+        widgets::{
+            arrival::{ArrivalState, SimpleArrivalDisplayable},
+            clock::ClockState,
+        },
+    };
+
+    use super::*;
+
+    struct FakeInstance {
+        priority: i32,
+    }
+
+    impl WidgetInstance for FakeInstance {
+        fn get_name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn get_priority(&self) -> i32 {
+            self.priority
+        }
+
+        fn get_visibility(&self, _now: DateTime<Utc>) -> Visibility {
+            Visibility::Visible
+        }
+
+        fn has_content(&self, _now: DateTime<Utc>) -> bool {
+            true
+        }
+
+        fn render(&self, _now: DateTime<Utc>, _canvas: &mut ScreenCanvas) {}
+    }
+
+    // Registered under the name of a real widget, with an update task that never sends
+    struct FakeWidget<C>(PhantomData<C>);
+
+    impl<C: LoadableWidget + 'static> Widget for FakeWidget<C> {
+        type Config = C;
+        type State = ();
+        const PRIORITY: i32 = PRIORITY_CLOCK;
+
+        fn initial_state() {}
+
+        fn spawn_update_task(
+            _key: String,
+            _widgets_rx: Receiver<Option<WidgetSnapshot>>,
+            _state_tx: Sender<()>,
+        ) -> JoinHandle<()> {
+            spawn(pending())
+        }
+
+        fn get_visibility(_state: &(), _now: DateTime<Utc>) -> Visibility {
+            Visibility::Visible
+        }
+
+        fn render(_state: &(), _now: DateTime<Utc>, _canvas: &mut ScreenCanvas) {}
+    }
+
+    fn at_second(second: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(second, 0).unwrap()
+    }
+
+    fn choose(
+        instances: &[(String, FakeInstance, Visibility)],
+        now: DateTime<Utc>,
+    ) -> Option<String> {
+        let visibilities: Vec<(&String, &dyn WidgetInstance, Visibility)> = instances
+            .iter()
+            .map(|(key, instance, visibility)| (key, instance as &dyn WidgetInstance, *visibility))
+            .collect();
+        choose_instance(&visibilities, Visibility::Visible, now)
+    }
+
+    fn list(widgets: &[(&str, &str)]) -> WidgetList {
+        widgets
+            .iter()
+            .map(|(key, name)| (key.to_string(), name.to_string()))
+            .collect()
+    }
+
+    // A real widget with a fixed state, without its update task
+    fn running<W: Widget>(state: W::State) -> Box<dyn WidgetInstance> {
+        let (_state_tx, state_rx) = watch::channel(state);
+        Box::new(RunningWidget::<W> {
+            state_rx,
+            task: spawn(pending()),
+        })
+    }
+
+    fn instance_keys(registry: &WidgetRegistry) -> Vec<&str> {
+        registry.instances.keys().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn choose_instance_prefers_the_highest_priority() {
+        let instances = [
+            (
+                String::from("a"),
+                FakeInstance {
+                    priority: PRIORITY_BOARD,
+                },
+                Visibility::Visible,
+            ),
+            (
+                String::from("b"),
+                FakeInstance {
+                    priority: PRIORITY_WEATHER,
+                },
+                Visibility::Visible,
+            ),
+            (
+                String::from("c"),
+                FakeInstance {
+                    priority: PRIORITY_ELEVATOR,
+                },
+                Visibility::Hidden,
+            ),
+        ];
+        assert_eq!(choose(&instances, at_second(0)), Some(String::from("b")));
+    }
+
+    #[test]
+    fn choose_instance_rotates_between_equal_priorities() {
+        let instances = [
+            (
+                String::from("a"),
+                FakeInstance {
+                    priority: PRIORITY_BOARD,
+                },
+                Visibility::Visible,
+            ),
+            (
+                String::from("b"),
+                FakeInstance {
+                    priority: PRIORITY_BOARD,
+                },
+                Visibility::Visible,
+            ),
+        ];
+        assert_eq!(choose(&instances, at_second(0)), Some(String::from("a")));
+        assert_eq!(
+            choose(&instances, at_second(ROTATION_SECONDS - 1)),
+            Some(String::from("a"))
+        );
+        assert_eq!(
+            choose(&instances, at_second(ROTATION_SECONDS)),
+            Some(String::from("b"))
+        );
+        assert_eq!(
+            choose(&instances, at_second(ROTATION_SECONDS * 2)),
+            Some(String::from("a"))
+        );
+    }
+
+    #[test]
+    fn choose_instance_without_candidates() {
+        let instances = [(
+            String::from("a"),
+            FakeInstance {
+                priority: PRIORITY_BOARD,
+            },
+            Visibility::WhenIdle,
+        )];
+        assert_eq!(choose(&instances, at_second(0)), None);
+    }
+
+    #[tokio::test]
+    async fn sync_starts_and_stops_instances() {
+        let (_widgets_tx, widgets_rx) = watch::channel(None);
+        let mut registry = WidgetRegistry::new(widgets_rx);
+        registry.register::<FakeWidget<ClockWidget>>();
+        registry.register::<FakeWidget<ElevatorWidget>>();
+
+        registry.sync(&list(&[
+            ("a", ClockWidget::NAME),
+            ("b", ElevatorWidget::NAME),
+            ("c", "unknown"),
+            ("d", BrightnessSettings::NAME),
+        ]));
+        assert_eq!(instance_keys(&registry), ["a", "b"]);
+
+        // Removed, and changed to another widget
+        registry.sync(&list(&[("b", ClockWidget::NAME)]));
+        assert_eq!(instance_keys(&registry), ["b"]);
+        assert_eq!(registry.instances["b"].get_name(), ClockWidget::NAME);
+    }
+
+    #[tokio::test]
+    async fn empty_bus_board_gives_way_to_idle_widgets() {
+        let (_widgets_tx, widgets_rx) = watch::channel(None);
+        let mut registry = WidgetRegistry::new(widgets_rx);
+        let empty_board = ArrivalState {
+            messages: vec![],
+            ..ArrivalState::loading()
+        };
+        registry.instances.insert(
+            String::from("bus"),
+            running::<BusDisplay>(Some(empty_board.clone())),
+        );
+        registry.instances.insert(
+            String::from("clock"),
+            running::<ClockDisplay>(ClockState {
+                show_when_idle: true,
+                ..ClockState::hidden()
+            }),
+        );
+        assert_eq!(
+            registry.choose_by_priority(at_second(0)),
+            Some(String::from("clock"))
+        );
+        assert!(!registry.instances["bus"].has_content(at_second(0)));
+
+        // Buses take the screen back from the clock once there are some
+        let board = ArrivalState {
+            messages: vec![SimpleArrivalDisplayable::loading()],
+            ..empty_board
+        };
+        registry
+            .instances
+            .insert(String::from("bus"), running::<BusDisplay>(Some(board)));
+        assert_eq!(
+            registry.choose_by_priority(at_second(0)),
+            Some(String::from("bus"))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::{collections::HashMap, error::Error};
+
+use cached::proc_macro::cached;
+use chrono::{DateTime, Datelike, NaiveTime, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::wmata::WmataClient;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PathApiReturn {
+    #[serde(rename(deserialize = "Path"))]
+    path: Vec<PathItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PathItem {
+    #[serde(rename(deserialize = "SeqNum"))]
+    seq_num: i64,
+    #[serde(rename(deserialize = "StationCode"))]
+    station_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StationToStationApiReturn {
+    #[serde(rename(deserialize = "StationToStationInfos"))]
+    station_to_station_infos: Vec<StationToStationInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StationToStationInfo {
+    #[serde(rename(deserialize = "RailTime"))]
+    rail_time: i64, // Minutes
+    #[serde(rename(deserialize = "RailFare"))]
+    rail_fare: RailFare,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RailFare {
+    #[serde(rename(deserialize = "PeakTime"))]
+    peak_time: f64,
+    #[serde(rename(deserialize = "OffPeakTime"))]
+    off_peak_time: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TripInfo {
+    pub rail_minutes: i64,
+    pub peak_fare: f64,
+    pub off_peak_fare: f64,
+}
+
+// Both fares of a trip, the one charged now first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fares {
+    pub current: f64,
+    pub other: f64,
+}
+
+impl TripInfo {
+    // Peak fares apply on weekdays from opening until 9:30 and from 15:00 to 19:00, Washington
+    // time. Late nights after midnight are off-peak.
+    pub fn get_fares(&self, now: DateTime<Utc>, timezone: Tz) -> Fares {
+        let now = now.with_timezone(&timezone);
+        let time = now.time();
+        let is_weekday = !matches!(now.weekday(), Weekday::Sat | Weekday::Sun);
+        let morning_peak =
+            NaiveTime::from_hms_opt(5, 0, 0).unwrap()..NaiveTime::from_hms_opt(9, 30, 0).unwrap();
+        let is_peak = morning_peak.contains(&time) || (15..19).contains(&time.hour());
+        match is_weekday && is_peak {
+            true => Fares {
+                current: self.peak_fare,
+                other: self.off_peak_fare,
+            },
+            false => Fares {
+                current: self.off_peak_fare,
+                other: self.peak_fare,
+            },
+        }
+    }
+}
+
+// Trips keyed by where a train is and where it's headed. None if it doesn't stop at the destination.
+pub type TripPlan = HashMap<(String, String), Option<TripInfo>>;
+
+const PATH_PATH: &str = "/Rail.svc/json/jPath";
+const STATION_TO_STATION_PATH: &str = "/Rail.svc/json/jSrcStationToDstStationInfo";
+
+// Paths and fares only change with the timetable, so they're loaded once a day
+#[cached(
+    time = 86400,
+    key = "String",
+    convert = r#"{ format!("{}-{}", from, to) }"#,
+    result = true
+)]
+async fn get_path(
+    client: &WmataClient,
+    from: &str,
+    to: &str,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let api_return: PathApiReturn = client
+        .get(
+            PATH_PATH,
+            &[("FromStationCode", from), ("ToStationCode", to)],
+        )
+        .await?;
+    Ok(api_return
+        .path
+        .into_iter()
+        .sorted_by_key(|item| item.seq_num)
+        .map(|item| item.station_code)
+        .collect())
+}
+
+#[cached(
+    time = 86400,
+    key = "String",
+    convert = r#"{ format!("{}-{}", from, to) }"#,
+    result = true
+)]
+async fn get_trip_info(
+    client: &WmataClient,
+    from: &str,
+    to: &str,
+) -> Result<TripInfo, Box<dyn Error + Send + Sync>> {
+    let api_return: StationToStationApiReturn = client
+        .get(
+            STATION_TO_STATION_PATH,
+            &[("FromStationCode", from), ("ToStationCode", to)],
+        )
+        .await?;
+    let info = api_return
+        .station_to_station_infos
+        .first()
+        .ok_or(format!("No trip info from {} to {}", from, to))?;
+    Ok(TripInfo {
+        rail_minutes: info.rail_time,
+        peak_fare: info.rail_fare.peak_time,
+        off_peak_fare: info.rail_fare.off_peak_time,
+    })
+}
+
+// The first destination code a train from `from` heading to `terminal` stops at, if any
+async fn find_stop(
+    client: &WmataClient,
+    from: &str,
+    terminal: &str,
+    destination_codes: &[String],
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let path = get_path(client, from, terminal).await?;
+    Ok(path
+        .into_iter()
+        .skip(1) // The station the train is at
+        .find(|code| destination_codes.contains(code)))
+}
+
+// Takes the (station, terminal) pair of each train
+pub async fn plan_trips(
+    client: &WmataClient,
+    trains: Vec<(String, String)>,
+    destination_codes: &[String],
+) -> TripPlan {
+    let mut plan = TripPlan::new();
+    for (from, terminal) in trains.into_iter().unique() {
+        // Lookups that fail are logged and the train is treated as not going there
+        let trip = match find_stop(client, &from, &terminal, destination_codes).await {
+            Ok(Some(stop)) => get_trip_info(client, &from, &stop)
+                .await
+                .inspect_err(|err| {
+                    warn!(target: "arrival_state_update", "Could not load trip info from {} to {}: {}", from, stop, err)
+                })
+                .ok(),
+            Ok(None) => None,
+            Err(err) => {
+                warn!(target: "arrival_state_update", "Could not load path from {} to {}: {}", from, terminal, err);
+                None
+            }
+        };
+        plan.insert((from, terminal), trip);
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone};
+    use chrono_tz::America::New_York;
+
+    use super::*;
+
+    const TRIP: TripInfo = TripInfo {
+        rail_minutes: 20,
+        peak_fare: 3.85,
+        off_peak_fare: 2.45,
+    };
+
+    // 6 January 2025 is a Monday
+    fn fares_at(day: u32, hour: u32, minute: u32) -> Fares {
+        let local = NaiveDate::from_ymd_opt(2025, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap();
+        let now = New_York.from_local_datetime(&local).unwrap();
+        TRIP.get_fares(now.with_timezone(&Utc), New_York)
+    }
+
+    fn peak() -> Fares {
+        Fares {
+            current: 3.85,
+            other: 2.45,
+        }
+    }
+
+    fn off_peak() -> Fares {
+        Fares {
+            current: 2.45,
+            other: 3.85,
+        }
+    }
+
+    #[test]
+    fn weekday_peaks() {
+        assert_eq!(fares_at(6, 5, 0), peak());
+        assert_eq!(fares_at(6, 9, 29), peak());
+        assert_eq!(fares_at(6, 9, 30), off_peak());
+        assert_eq!(fares_at(6, 12, 0), off_peak());
+        assert_eq!(fares_at(6, 15, 0), peak());
+        assert_eq!(fares_at(6, 19, 0), off_peak());
+    }
+
+    #[test]
+    fn after_midnight_is_off_peak() {
+        assert_eq!(fares_at(6, 0, 30), off_peak());
+    }
+
+    #[test]
+    fn weekends_are_off_peak() {
+        assert_eq!(fares_at(11, 8, 0), off_peak());
+        assert_eq!(fares_at(12, 17, 0), off_peak());
+    }
+}
@@ -0,0 +1,732 @@
+use std::{env, error::Error, fmt::Debug, future::Future, time::Duration};
+
+use cached::proc_macro::cached;
+use chrono::{DateTime, Utc};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_4X6, FONT_5X8, FONT_6X10},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::Rgb888,
+    prelude::{DrawTarget, Point, Primitive, RgbColor, Size},
+    primitives::{Circle, PrimitiveStyle, Rectangle},
+    text::Text,
+    Drawable,
+};
+use log::{debug, error, info};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    spawn,
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    firebase::{get_latest_widget, WeatherWidget, WidgetSnapshot},
+    led::ScreenCanvas,
+};
+
+use super::{
+    get_screen_size,
+    registry::{Visibility, Widget, PRIORITY_WEATHER},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeatherIcon {
+    Sun,
+    Moon,
+    PartlyCloudy,
+    Cloudy,
+    Rain,
+    Snow,
+    Thunder,
+    Fog,
+}
+
+#[derive(Clone, Debug)]
+pub struct ForecastPeriod {
+    pub label: String, // e.g. "TUE", or "NGT" for overnight periods
+    pub temperature: i64,
+    pub temperature_unit: String,
+    pub conditions: String,
+    pub icon: WeatherIcon,
+}
+
+#[derive(Clone, Debug)]
+pub struct Forecast {
+    pub current: ForecastPeriod,
+    pub upcoming: Vec<ForecastPeriod>,
+}
+
+pub trait ForecastProvider {
+    fn get_forecast(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        use_metric: bool,
+    ) -> impl Future<Output = Result<Forecast, Box<dyn Error + Send + Sync>>> + Send;
+}
+
+// These mirror the GeoJSON responses of api.weather.gov, trimmed to what the widget shows
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PointsApiReturn {
+    properties: PointProperties,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PointProperties {
+    #[serde(rename(deserialize = "forecast"))]
+    forecast_url: String,
+    #[serde(rename(deserialize = "forecastHourly"))]
+    forecast_hourly_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ForecastApiReturn {
+    properties: ForecastProperties,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ForecastProperties {
+    periods: Vec<ForecastApiPeriod>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ForecastApiPeriod {
+    #[serde(rename(deserialize = "startTime"))]
+    start_time: String,
+    #[serde(rename(deserialize = "isDaytime"))]
+    is_daytime: bool,
+    temperature: i64,
+    #[serde(rename(deserialize = "temperatureUnit"))]
+    temperature_unit: String,
+    #[serde(rename(deserialize = "shortForecast"))]
+    short_forecast: String,
+}
+
+impl ForecastApiPeriod {
+    fn to_forecast_period(&self) -> ForecastPeriod {
+        let label = match self.is_daytime {
+            true => DateTime::parse_from_rfc3339(&self.start_time)
+                .map(|start| start.format("%a").to_string().to_uppercase())
+                .unwrap_or_default(),
+            false => String::from("NGT"),
+        };
+        ForecastPeriod {
+            label,
+            temperature: self.temperature,
+            temperature_unit: self.temperature_unit.clone(),
+            conditions: self.short_forecast.clone(),
+            icon: get_weather_icon(&self.short_forecast, self.is_daytime),
+        }
+    }
+}
+
+// weather.gov only describes conditions in words, e.g. "Chance Showers And Thunderstorms"
+fn get_weather_icon(short_forecast: &str, is_daytime: bool) -> WeatherIcon {
+    let conditions = short_forecast.to_lowercase();
+    let mentions = |words: &[&str]| words.iter().any(|word| conditions.contains(word));
+    if mentions(&["thunder"]) {
+        WeatherIcon::Thunder
+    } else if mentions(&["snow", "sleet", "flurries", "ice"]) {
+        WeatherIcon::Snow
+    } else if mentions(&["rain", "shower", "drizzle"]) {
+        WeatherIcon::Rain
+    } else if mentions(&["fog", "haze", "smoke", "mist"]) {
+        WeatherIcon::Fog
+    } else if mentions(&["partly", "mostly sunny", "mostly clear"]) {
+        WeatherIcon::PartlyCloudy
+    } else if mentions(&["cloudy", "overcast"]) {
+        WeatherIcon::Cloudy
+    } else if is_daytime {
+        WeatherIcon::Sun
+    } else {
+        WeatherIcon::Moon
+    }
+}
+
+const DEFAULT_WEATHER_API_URL: &str = "https://api.weather.gov";
+const DEFAULT_USER_AGENT: &str = "arrivalsign";
+
+// api.weather.gov asks every client to identify itself with a User-Agent.
+// WEATHER_API_URL points it at a local stand-in serving fixtures.
+#[derive(Clone, Debug)]
+pub struct WeatherGovProvider {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl WeatherGovProvider {
+    pub fn from_env() -> Self {
+        let user_agent = env::var("WEATHER_USER_AGENT").unwrap_or(DEFAULT_USER_AGENT.to_string());
+        WeatherGovProvider {
+            base_url: env::var("WEATHER_API_URL")
+                .unwrap_or(DEFAULT_WEATHER_API_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            client: reqwest::Client::builder()
+                .user_agent(user_agent)
+                .build()
+                .unwrap(),
+        }
+    }
+
+    // Forecast links in responses are absolute. Only their path is kept, so a stand-in is used throughout.
+    fn rebase_url(&self, url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let parsed = reqwest::Url::parse(url)?;
+        Ok(format!("{}{}", self.base_url, parsed.path()))
+    }
+}
+
+async fn get_json<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    query: &[(&str, &str)],
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    let raw = client
+        .get(url)
+        .query(query)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let raw_string = String::from_utf8(raw.to_vec())?;
+    debug!(target: "weather", "{} {}", url, raw_string);
+    Ok(serde_json::from_str(&raw_string)?)
+}
+
+// A point's forecast office and grid don't change, so they're looked up once a day
+#[cached(
+    time = 86400,
+    key = "String",
+    convert = r#"{ points_url.to_string() }"#,
+    result = true
+)]
+async fn get_point_properties(
+    client: &reqwest::Client,
+    points_url: &str,
+) -> Result<PointProperties, Box<dyn Error + Send + Sync>> {
+    let api_return: PointsApiReturn = get_json(client, points_url, &[]).await?;
+    Ok(api_return.properties)
+}
+
+impl ForecastProvider for WeatherGovProvider {
+    async fn get_forecast(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        use_metric: bool,
+    ) -> Result<Forecast, Box<dyn Error + Send + Sync>> {
+        // weather.gov redirects points with more than four decimals
+        let points_url = format!("{}/points/{:.4},{:.4}", self.base_url, latitude, longitude);
+        let point = get_point_properties(&self.client, &points_url).await?;
+        let units = [("units", if use_metric { "si" } else { "us" })];
+
+        let hourly: ForecastApiReturn = get_json(
+            &self.client,
+            &self.rebase_url(&point.forecast_hourly_url)?,
+            &units,
+        )
+        .await?;
+        let daily: ForecastApiReturn =
+            get_json(&self.client, &self.rebase_url(&point.forecast_url)?, &units).await?;
+        let current = hourly
+            .properties
+            .periods
+            .first()
+            .ok_or("Hourly forecast has no periods")?
+            .to_forecast_period();
+        Ok(Forecast {
+            current,
+            upcoming: daily
+                .properties
+                .periods
+                .iter()
+                .take(UPCOMING_PERIODS)
+                .map(ForecastApiPeriod::to_forecast_period)
+                .collect(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WeatherState {
+    pub visible: bool,
+    pub forecast: Option<Forecast>,
+}
+
+impl WeatherState {
+    pub fn hidden() -> Self {
+        WeatherState {
+            visible: false,
+            forecast: None,
+        }
+    }
+}
+
+const UPCOMING_PERIODS: usize = 3;
+const ICON_SIZE: i32 = 12;
+const FORECAST_HEIGHT: i32 = 31; // From the line above it to the bottom of the screen
+
+// How big the current conditions are drawn, largest first
+struct ConditionsSize {
+    scale: u32, // Of the icon
+    temperature_font: &'static MonoFont<'static>,
+    small_font: &'static MonoFont<'static>, // The unit and the description
+}
+
+impl ConditionsSize {
+    // From the top of the icon to the bottom of the description under the temperature
+    fn get_height(&self) -> i32 {
+        ICON_SIZE * self.scale as i32 + 2 + self.small_font.character_size.height as i32
+            - self.small_font.baseline as i32
+    }
+}
+
+static CONDITIONS_SIZES: [ConditionsSize; 2] = [
+    ConditionsSize {
+        scale: 2,
+        temperature_font: &FONT_10X20,
+        small_font: &FONT_6X10,
+    },
+    ConditionsSize {
+        scale: 1,
+        temperature_font: &FONT_6X10,
+        small_font: &FONT_4X6,
+    },
+];
+const DEFAULT_DISPLAY_SECONDS: u64 = 10;
+const DEFAULT_INTERVAL_SECONDS: u64 = 300;
+
+pub fn spawn_weather_update_task<P>(
+    provider: P,
+    key: String,
+    widgets_rx: Receiver<Option<WidgetSnapshot>>,
+    state_tx: Sender<WeatherState>,
+) -> JoinHandle<()>
+where
+    P: ForecastProvider + Send + Sync + 'static,
+{
+    spawn(async move {
+        tokio::time::sleep(Duration::from_secs(40)).await;
+        loop {
+            debug!(target: "weather_state_update", "Loading new state...");
+            let snapshot = widgets_rx.borrow().clone();
+            let Some(widget) = get_latest_widget::<WeatherWidget>(snapshot, &key).await else {
+                debug!(target: "weather_state_update", "No weather widget configured.");
+                tokio::time::sleep(Duration::from_secs(DEFAULT_INTERVAL_SECONDS)).await;
+                continue;
+            };
+            match provider
+                .get_forecast(widget.latitude, widget.longitude, widget.uses_metric())
+                .await
+            {
+                Ok(forecast) => {
+                    info!(target: "weather_state_update", "New state loaded. Sending to main thread.");
+                    let state = WeatherState {
+                        visible: true,
+                        forecast: Some(forecast),
+                    };
+                    state_tx.send(state.clone()).unwrap();
+                    tokio::time::sleep(Duration::from_secs(
+                        widget.display_seconds.unwrap_or(DEFAULT_DISPLAY_SECONDS),
+                    ))
+                    .await;
+                    // Keep the forecast around for a playlist
+                    state_tx
+                        .send(WeatherState {
+                            visible: false,
+                            ..state
+                        })
+                        .unwrap();
+                }
+                Err(err) => {
+                    error!(target: "weather_state_update", "Could not load the forecast: {}", err);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(
+                widget.interval_seconds.unwrap_or(DEFAULT_INTERVAL_SECONDS),
+            ))
+            .await;
+        }
+    })
+}
+
+// 12x12 icons. Y sun, M moon, W cloud, B rain, C snow.
+const SUN_ICON: [&str; 12] = [
+    "     Y      ",
+    " Y   Y   Y  ",
+    "  Y     Y   ",
+    "    YYY     ",
+    "   YYYYY    ",
+    "YY YYYYY YY ",
+    "   YYYYY    ",
+    "    YYY     ",
+    "  Y     Y   ",
+    " Y   Y   Y  ",
+    "     Y      ",
+    "            ",
+];
+
+const MOON_ICON: [&str; 12] = [
+    "            ",
+    "   MMM      ",
+    "  MM        ",
+    " MM         ",
+    " MM         ",
+    " MM         ",
+    " MM         ",
+    " MMM     M  ",
+    "  MMM  MMM  ",
+    "   MMMMMM   ",
+    "            ",
+    "            ",
+];
+
+const PARTLY_CLOUDY_ICON: [&str; 12] = [
+    " Y   Y      ",
+    "   YYY      ",
+    "  YYYYY     ",
+    "Y YYYWWW    ",
+    "  YYWWWWW   ",
+    "   WWWWWWWW ",
+    "  WWWWWWWWWW",
+    "  WWWWWWWWWW",
+    "   WWWWWWWW ",
+    "            ",
+    "            ",
+    "            ",
+];
+
+const CLOUDY_ICON: [&str; 12] = [
+    "            ",
+    "            ",
+    "            ",
+    "    WWW     ",
+    "   WWWWW    ",
+    " WWWWWWWWW  ",
+    "WWWWWWWWWWW ",
+    "WWWWWWWWWWW ",
+    " WWWWWWWWW  ",
+    "            ",
+    "            ",
+    "            ",
+];
+
+const RAIN_ICON: [&str; 12] = [
+    "    WWW     ",
+    "   WWWWW    ",
+    " WWWWWWWWW  ",
+    "WWWWWWWWWWW ",
+    "WWWWWWWWWWW ",
+    " WWWWWWWWW  ",
+    "            ",
+    " B   B   B  ",
+    "B   B   B   ",
+    "            ",
+    "  B   B   B ",
+    " B   B   B  ",
+];
+
+const SNOW_ICON: [&str; 12] = [
+    "    WWW     ",
+    "   WWWWW    ",
+    " WWWWWWWWW  ",
+    "WWWWWWWWWWW ",
+    "WWWWWWWWWWW ",
+    " WWWWWWWWW  ",
+    "            ",
+    "  C     C   ",
+    " CCC   CCC  ",
+    "  C  C  C   ",
+    "    CCC     ",
+    "     C      ",
+];
+
+const THUNDER_ICON: [&str; 12] = [
+    "    WWW     ",
+    "   WWWWW    ",
+    " WWWWWWWWW  ",
+    "WWWWWWWWWWW ",
+    "WWWWWWWWWWW ",
+    " WWWWWWWWW  ",
+    "     YY     ",
+    "    YY      ",
+    "   YYYY     ",
+    "     YY     ",
+    "    YY      ",
+    "   Y        ",
+];
+
+const FOG_ICON: [&str; 12] = [
+    "            ",
+    "            ",
+    "WWWWWWWWWW  ",
+    "            ",
+    "  WWWWWWWWWW",
+    "            ",
+    "WWWWWWWWWW  ",
+    "            ",
+    "  WWWWWWWWWW",
+    "            ",
+    "            ",
+    "            ",
+];
+
+fn get_icon_pixels(icon: WeatherIcon) -> &'static [&'static str; 12] {
+    match icon {
+        WeatherIcon::Sun => &SUN_ICON,
+        WeatherIcon::Moon => &MOON_ICON,
+        WeatherIcon::PartlyCloudy => &PARTLY_CLOUDY_ICON,
+        WeatherIcon::Cloudy => &CLOUDY_ICON,
+        WeatherIcon::Rain => &RAIN_ICON,
+        WeatherIcon::Snow => &SNOW_ICON,
+        WeatherIcon::Thunder => &THUNDER_ICON,
+        WeatherIcon::Fog => &FOG_ICON,
+    }
+}
+
+fn get_icon_color(pixel: char) -> Option<Rgb888> {
+    match pixel {
+        'Y' => Some(Rgb888::new(255, 200, 0)),
+        'M' => Some(Rgb888::new(230, 230, 180)),
+        'W' => Some(Rgb888::new(170, 170, 170)),
+        'B' => Some(Rgb888::new(0, 110, 255)),
+        'C' => Some(Rgb888::new(200, 230, 255)),
+        _ => None,
+    }
+}
+
+// Each icon pixel is drawn as a scale x scale square
+fn draw_icon<D>(icon: WeatherIcon, top_left: Point, scale: u32, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    for (y, row) in get_icon_pixels(icon).iter().enumerate() {
+        for (x, pixel) in row.chars().enumerate() {
+            if let Some(color) = get_icon_color(pixel) {
+                Rectangle::new(
+                    top_left + Point::new(x as i32, y as i32) * scale as i32,
+                    Size::new(scale, scale),
+                )
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(canvas)
+                .unwrap();
+            }
+        }
+    }
+}
+
+pub fn render_weather_display<D>(state: WeatherState, canvas: &mut D)
+where
+    D: DrawTarget<Color = Rgb888>,
+    <D as DrawTarget>::Error: Debug,
+{
+    let (screen_width, screen_height) = get_screen_size(canvas);
+    let Some(forecast) = state.forecast else {
+        return;
+    };
+
+    // The forecast goes along the bottom if there's room for it under the big icon, or the small
+    // one on shorter panels. Without it the current conditions get the whole screen.
+    let fits = |size: &ConditionsSize, height: i32| size.get_height() <= height;
+    let (size, divider_y) = match CONDITIONS_SIZES
+        .iter()
+        .find(|size| fits(size, screen_height - FORECAST_HEIGHT))
+    {
+        Some(size) => (size, Some(screen_height - FORECAST_HEIGHT)),
+        None => (
+            CONDITIONS_SIZES
+                .iter()
+                .find(|size| fits(size, screen_height))
+                .unwrap_or(&CONDITIONS_SIZES[CONDITIONS_SIZES.len() - 1]),
+            None,
+        ),
+    };
+    let conditions_height = divider_y.unwrap_or(screen_height);
+    let gray_text_style = MonoTextStyle::new(size.small_font, Rgb888::new(120, 120, 120));
+    let small_text_style = MonoTextStyle::new(&FONT_5X8, Rgb888::WHITE);
+
+    // Current conditions: big icon, temperature with a degree sign, and the description
+    let top = (conditions_height - size.get_height()) / 2;
+    let scale = size.scale as i32;
+    draw_icon(
+        forecast.current.icon,
+        Point::new(2, top),
+        size.scale,
+        canvas,
+    );
+    let text_x = 2 + (ICON_SIZE + 3) * scale;
+    let temperature = forecast.current.temperature.to_string();
+    let degree_x =
+        text_x + temperature.len() as i32 * size.temperature_font.character_size.width as i32;
+    let degree_size = 2 + size.scale;
+    Text::new(
+        &temperature,
+        Point::new(text_x, top + size.temperature_font.baseline as i32),
+        MonoTextStyle::new(size.temperature_font, Rgb888::WHITE),
+    )
+    .draw(canvas)
+    .unwrap();
+    Circle::new(Point::new(degree_x + 1, top + 1), degree_size)
+        .into_styled(PrimitiveStyle::with_stroke(Rgb888::WHITE, 1))
+        .draw(canvas)
+        .unwrap();
+    Text::new(
+        &forecast.current.temperature_unit,
+        Point::new(
+            degree_x + degree_size as i32 + 2,
+            top + size.small_font.baseline as i32 + 2,
+        ),
+        MonoTextStyle::new(size.small_font, Rgb888::WHITE),
+    )
+    .draw(canvas)
+    .unwrap();
+    let conditions_columns =
+        ((screen_width - text_x) / size.small_font.character_size.width as i32).max(0) as usize;
+    Text::new(
+        &forecast
+            .current
+            .conditions
+            .chars()
+            .take(conditions_columns)
+            .collect::<String>(),
+        Point::new(text_x, top + ICON_SIZE * scale + 2),
+        gray_text_style,
+    )
+    .draw(canvas)
+    .unwrap();
+
+    let Some(divider_y) = divider_y else {
+        return;
+    };
+
+    // Line above the forecast
+    Rectangle::with_corners(
+        Point::new(0, divider_y),
+        Point::new(screen_width, divider_y),
+    )
+    .into_styled(PrimitiveStyle::with_fill(Rgb888::new(40, 40, 40)))
+    .draw(canvas)
+    .unwrap();
+
+    // Upcoming periods in columns
+    let column_width = screen_width / UPCOMING_PERIODS as i32;
+    for (index, period) in forecast.upcoming.iter().enumerate() {
+        let left = index as i32 * column_width;
+        draw_icon(period.icon, Point::new(left + 1, divider_y + 6), 1, canvas);
+        Text::new(
+            &period.label,
+            Point::new(left + ICON_SIZE + 3, divider_y + 11),
+            MonoTextStyle::new(&FONT_5X8, Rgb888::new(120, 120, 120)),
+        )
+        .draw(canvas)
+        .unwrap();
+        Text::new(
+            &format!("{}{}", period.temperature, period.temperature_unit),
+            Point::new(left + ICON_SIZE + 3, divider_y + 20),
+            small_text_style,
+        )
+        .draw(canvas)
+        .unwrap();
+    }
+}
+
+// Current conditions and forecast, shown now and then
+pub struct WeatherDisplay;
+
+impl Widget for WeatherDisplay {
+    type Config = WeatherWidget;
+    type State = WeatherState;
+    const PRIORITY: i32 = PRIORITY_WEATHER;
+
+    fn initial_state() -> WeatherState {
+        WeatherState::hidden()
+    }
+
+    fn spawn_update_task(
+        key: String,
+        widgets_rx: Receiver<Option<WidgetSnapshot>>,
+        state_tx: Sender<WeatherState>,
+    ) -> JoinHandle<()> {
+        spawn_weather_update_task(WeatherGovProvider::from_env(), key, widgets_rx, state_tx)
+    }
+
+    fn get_visibility(state: &WeatherState, _now: DateTime<Utc>) -> Visibility {
+        match state.visible {
+            true => Visibility::Visible,
+            false => Visibility::Hidden,
+        }
+    }
+
+    fn has_content(state: &WeatherState, _now: DateTime<Utc>) -> bool {
+        state.forecast.is_some()
+    }
+
+    fn render(state: &WeatherState, _now: DateTime<Utc>, canvas: &mut ScreenCanvas) {
+        render_weather_display(state.clone(), canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weather_icons_from_short_forecasts() {
+        let day = [
+            ("Chance Showers And Thunderstorms", WeatherIcon::Thunder),
+            ("Rain And Snow Likely", WeatherIcon::Snow),
+            ("Slight Chance Light Rain", WeatherIcon::Rain),
+            ("Patchy Fog", WeatherIcon::Fog),
+            ("Partly Sunny", WeatherIcon::PartlyCloudy),
+            ("Mostly Sunny", WeatherIcon::PartlyCloudy),
+            ("Mostly Cloudy", WeatherIcon::Cloudy),
+            ("Sunny", WeatherIcon::Sun),
+        ];
+        for (short_forecast, icon) in day {
+            assert_eq!(
+                get_weather_icon(short_forecast, true),
+                icon,
+                "{}",
+                short_forecast
+            );
+        }
+    }
+
+    #[test]
+    fn clear_nights_get_the_moon() {
+        assert_eq!(get_weather_icon("Clear", false), WeatherIcon::Moon);
+        assert_eq!(
+            get_weather_icon("Mostly Clear", false),
+            WeatherIcon::PartlyCloudy
+        );
+        assert_eq!(get_weather_icon("Clear", true), WeatherIcon::Sun);
+    }
+
+    #[test]
+    fn periods_are_labelled_by_weekday() {
+        let period = |start_time: &str, is_daytime| ForecastApiPeriod {
+            start_time: start_time.to_string(),
+            is_daytime,
+            temperature: 54,
+            temperature_unit: String::from("F"),
+            short_forecast: String::from("Sunny"),
+        };
+        assert_eq!(
+            period("2025-01-07T06:00:00-05:00", true)
+                .to_forecast_period()
+                .label,
+            "TUE"
+        );
+        assert_eq!(
+            period("2025-01-07T18:00:00-05:00", false)
+                .to_forecast_period()
+                .label,
+            "NGT"
+        );
+    }
+}
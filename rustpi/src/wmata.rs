@@ -0,0 +1,48 @@
+use std::{env, error::Error};
+
+use log::debug;
+use serde::de::DeserializeOwned;
+
+const DEFAULT_API_URL: &str = "https://api.wmata.com";
+const API_KEY_HEADER: &str = "api_key";
+
+// Shared by everything that talks to api.wmata.com. WMATA_API_URL points it at a local mock.
+#[derive(Clone, Debug)]
+pub struct WmataClient {
+    base_url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl WmataClient {
+    pub fn from_env() -> Self {
+        WmataClient {
+            base_url: env::var("WMATA_API_URL")
+                .unwrap_or(DEFAULT_API_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            api_key: env::var("WMATA_API_KEY").unwrap_or_default(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        let raw = self
+            .client
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .header(API_KEY_HEADER, &self.api_key)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let raw_string = String::from_utf8(raw.to_vec())?;
+        debug!(target: "wmata", "{} {}", path, raw_string);
+        Ok(serde_json::from_str(&raw_string)?)
+    }
+}